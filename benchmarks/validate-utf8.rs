@@ -4,35 +4,80 @@
 
 #[macro_use]
 extern crate criterion;
-use criterion::{Benchmark, Criterion, Throughput};
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use is_utf8::{lemire, lookup, range, Backend};
+
+// Benches an x86 backend, if the CPU supports it.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+macro_rules! bench_x86 {
+    ($group:expr, $backend:ident, $func:path, $bytes:expr) => {
+        if Backend::$backend.is_available() {
+            // Some are safe where their target features are enabled at
+            // compile time.
+            #[allow(unused_unsafe)]
+            $group.bench_function(Backend::$backend.name(), |b| {
+                b.iter(|| unsafe { $func($bytes) })
+            });
+        }
+    };
+}
 
 macro_rules! bench {
     ($name:ident, $path:expr) => {
         fn $name(c: &mut Criterion) {
             let bytes = include_bytes!($path);
 
-            c.bench(
-                stringify!($name),
-                Benchmark::new("libcore", move |b| {
-                    b.iter(|| ::is_utf8::libcore::is_utf8(bytes))
-                })
-                .with_function("lemire_sse", move |b| {
-                    b.iter(|| ::is_utf8::lemire::sse::is_utf8(bytes))
-                })
-                .with_function("lemire_avx", move |b| {
-                    b.iter(|| ::is_utf8::lemire::avx::is_utf8(bytes))
-                })
-                .with_function("lemire_avx_ascii_path", move |b| {
-                    b.iter(|| ::is_utf8::lemire::avx::is_utf8_ascii_path(bytes))
-                })
-                .with_function("range_sse", move |b| {
-                    b.iter(|| ::is_utf8::range::sse::is_utf8(bytes))
-                })
-                .with_function("range_avx", move |b| {
-                    b.iter(|| ::is_utf8::range::avx::is_utf8(bytes))
-                })
-                .throughput(Throughput::Bytes(bytes.len() as u64)),
-            );
+            let mut group = c.benchmark_group(stringify!($name));
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_function("libcore", |b| b.iter(|| ::is_utf8::libcore::is_utf8(bytes)));
+            group.bench_function("swar", |b| b.iter(|| ::is_utf8::swar::is_utf8(bytes)));
+            #[cfg(feature = "portable-simd")]
+            group.bench_function("lookup_portable", |b| {
+                b.iter(|| ::is_utf8::lookup::portable::is_utf8(bytes))
+            });
+            group.bench_function("dispatch", |b| b.iter(|| ::is_utf8::is_utf8(bytes)));
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                bench_x86!(group, LemireSse, lemire::sse::is_utf8, bytes);
+                bench_x86!(group, LemireAvx, lemire::avx::is_utf8, bytes);
+                bench_x86!(
+                    group,
+                    LemireAvxAsciiPath,
+                    lemire::avx::is_utf8_ascii_path,
+                    bytes
+                );
+                bench_x86!(
+                    group,
+                    LemireSseAsciiPath,
+                    lemire::sse::is_utf8_ascii_path,
+                    bytes
+                );
+                bench_x86!(group, RangeSse, range::sse::is_utf8, bytes);
+                bench_x86!(group, RangeSse2, range::sse2::is_utf8, bytes);
+                bench_x86!(
+                    group,
+                    RangeSseAsciiPath,
+                    range::sse::is_utf8_ascii_path,
+                    bytes
+                );
+                bench_x86!(group, RangeAvx, range::avx::is_utf8, bytes);
+                bench_x86!(
+                    group,
+                    RangeAvxAsciiPath,
+                    range::avx::is_utf8_ascii_path,
+                    bytes
+                );
+                bench_x86!(group, LookupSse, lookup::sse::is_utf8, bytes);
+                bench_x86!(group, LookupAvx, lookup::avx::is_utf8, bytes);
+                bench_x86!(
+                    group,
+                    LookupAvxAsciiPath,
+                    lookup::avx::is_utf8_ascii_path,
+                    bytes
+                );
+            }
+            group.finish();
         }
     };
 }
@@ -47,6 +92,57 @@ bench!(
     "../props/utf8-characters-0-0x10ffff-with-garbage.bin"
 );
 
+// Inputs of 0 to 128 bytes, where setting up the vector code is a large part
+// of the cost.
+macro_rules! bench_sizes {
+    ($name:ident, $path:expr) => {
+        fn $name(c: &mut Criterion) {
+            let text: &str = include_str!($path);
+
+            let mut group = c.benchmark_group(stringify!($name));
+            for &size in &[0, 4, 8, 12, 16, 24, 32, 48, 64, 96, 128] {
+                let mut end = size;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let bytes = &text.as_bytes()[..end];
+                group.throughput(Throughput::Bytes(bytes.len() as u64));
+                group.bench_with_input(BenchmarkId::new("libcore", size), bytes, |b, bytes| {
+                    b.iter(|| ::is_utf8::libcore::is_utf8(black_box(bytes)))
+                });
+                group.bench_with_input(BenchmarkId::new("dispatch", size), bytes, |b, bytes| {
+                    b.iter(|| ::is_utf8::is_utf8(black_box(bytes)))
+                });
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                {
+                    if Backend::LookupSse.is_available() {
+                        let id = BenchmarkId::new(Backend::LookupSse.name(), size);
+                        group.bench_with_input(id, bytes, |b, bytes| {
+                            b.iter(|| unsafe { lookup::sse::is_utf8(black_box(bytes)) })
+                        });
+                    }
+                    if Backend::LookupAvx.is_available() {
+                        let id = BenchmarkId::new(Backend::LookupAvx.name(), size);
+                        group.bench_with_input(id, bytes, |b, bytes| {
+                            b.iter(|| unsafe { lookup::avx::is_utf8(black_box(bytes)) })
+                        });
+                    }
+                    if Backend::RangeAvx.is_available() {
+                        let id = BenchmarkId::new(Backend::RangeAvx.name(), size);
+                        group.bench_with_input(id, bytes, |b, bytes| {
+                            b.iter(|| unsafe { range::avx::is_utf8(black_box(bytes)) })
+                        });
+                    }
+                }
+            }
+            group.finish();
+        }
+    };
+}
+
+bench_sizes!(sizes_ascii, "../props/ascii_sample_ok.txt");
+bench_sizes!(sizes_utf8, "../props/utf8_sample_ok.txt");
+
 criterion_group!(
     benches,
     random_bytes,
@@ -54,6 +150,8 @@ criterion_group!(
     ascii,
     utf8,
     all_utf8,
-    all_utf8_with_garbage
+    all_utf8_with_garbage,
+    sizes_ascii,
+    sizes_utf8
 );
 criterion_main!(benches);
@@ -20,6 +20,8 @@ run() {
       --env NORUN \
       --env NOVERIFY \
       --env RUSTFLAGS \
+      --env FEATURES \
+      --env VLEN \
       --volume `pwd`:/checkout:ro \
       --volume `pwd`/target:/checkout/target \
       --workdir /checkout \
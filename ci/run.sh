@@ -18,6 +18,8 @@ cargo --version
 echo "TARGET=${TARGET}"
 echo "HOST=${HOST}"
 echo "RUSTFLAGS=${RUSTFLAGS}"
+echo "FEATURES=${FEATURES}"
+echo "VLEN=${VLEN}"
 echo "NORUN=${NORUN}"
 echo "NOVERIFY=${NOVERIFY}"
 echo "CARGO_SUBCMD=${CARGO_SUBCMD}"
@@ -27,8 +29,13 @@ echo "RUST_TEST_THREADS=${RUST_TEST_THREADS}"
 echo "RUST_BACKTRACE=${RUST_BACKTRACE}"
 echo "RUST_TEST_NOCAPTURE=${RUST_TEST_NOCAPTURE}"
 
+# The vector length qemu emulates on RISC-V
+if [[ -n "${VLEN}" ]]; then
+    export CARGO_TARGET_RISCV64GC_UNKNOWN_LINUX_GNU_RUNNER="qemu-riscv64 -cpu rv64,v=true,vlen=${VLEN} -L /usr/riscv64-linux-gnu"
+fi
+
 cargo_test() {
-    cmd="cargo ${CARGO_SUBCMD} --verbose --target=${TARGET} ${@}"
+    cmd="cargo ${CARGO_SUBCMD} --verbose --target=${TARGET} --features=${FEATURES} ${@}"
     mkdir target || true
     ${cmd} 2>&1 | tee > target/output
     if [[ ${PIPESTATUS[0]} != 0 ]]; then
@@ -1,21 +1,22 @@
 #![no_main]
-use is_utf8::{lemire, libcore, range};
+use is_utf8::{available_backends, is_utf8_with, libcore};
 use libfuzzer_sys::fuzz_target;
 
 fuzz_target!(|data: &[u8]| {
-    let lemire_avx_ascii = lemire::avx::is_utf8_ascii_path(data);
-    let lemire_avx = lemire::avx::is_utf8(data);
-    let lemire_sse = lemire::sse::is_utf8(data);
-    let range_avx = range::avx::is_utf8(data);
-    let range_sse = range::sse::is_utf8(data);
     let libcore = libcore::is_utf8(data);
+    let dispatch = is_utf8::is_utf8(data);
 
-    // Make sure that each implementation is in agreement with the others. I
-    // chain the assert statements together like since to hopefully return a
-    // more readable error in case of a panic.
-    assert_eq!(lemire_avx_ascii, lemire_avx);
-    assert_eq!(lemire_avx, lemire_sse);
-    assert_eq!(lemire_sse, range_avx);
-    assert_eq!(range_avx, range_sse);
-    assert_eq!(range_sse, libcore);
+    // Make sure that each implementation the CPU supports is in agreement
+    // with libcore.
+    for backend in available_backends() {
+        assert_eq!(is_utf8_with(backend, data), libcore, "{}", backend.name());
+    }
+    assert_eq!(libcore, dispatch);
+
+    // The located error must be the same one the standard library reports.
+    let expected = std::str::from_utf8(data)
+        .map(|_| ())
+        .map_err(|e| (e.valid_up_to(), e.error_len()));
+    let validate = is_utf8::validate(data).map_err(|e| (e.valid_up_to(), e.error_len()));
+    assert_eq!(validate, expected);
 });
@@ -0,0 +1,492 @@
+//! The available implementations, and a common interface to them.
+use crate::{cpu, libcore, swar, ParseBackendError, Utf8Error};
+use core::str::FromStr;
+
+/// An implementation of UTF-8 validation.
+///
+/// Each variant is one function of the [`lemire`](crate::lemire),
+/// [`lookup`](crate::lookup), [`range`](crate::range), [`swar`](crate::swar)
+/// or [`libcore`](crate::libcore) modules.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// [`lookup::avx::is_utf8`](crate::lookup::avx::is_utf8)
+    LookupAvx,
+    /// [`lookup::avx::is_utf8_ascii_path`](crate::lookup::avx::is_utf8_ascii_path)
+    LookupAvxAsciiPath,
+    /// [`lemire::avx::is_utf8_ascii_path`](crate::lemire::avx::is_utf8_ascii_path)
+    LemireAvxAsciiPath,
+    /// [`lemire::avx::is_utf8`](crate::lemire::avx::is_utf8)
+    LemireAvx,
+    /// [`range::avx::is_utf8_ascii_path`](crate::range::avx::is_utf8_ascii_path)
+    RangeAvxAsciiPath,
+    /// [`range::avx::is_utf8`](crate::range::avx::is_utf8)
+    RangeAvx,
+    /// [`lookup::sse::is_utf8`](crate::lookup::sse::is_utf8)
+    LookupSse,
+    /// [`range::sse::is_utf8_ascii_path`](crate::range::sse::is_utf8_ascii_path)
+    RangeSseAsciiPath,
+    /// [`range::sse::is_utf8`](crate::range::sse::is_utf8)
+    RangeSse,
+    /// [`lemire::sse::is_utf8_ascii_path`](crate::lemire::sse::is_utf8_ascii_path)
+    LemireSseAsciiPath,
+    /// [`lemire::sse::is_utf8`](crate::lemire::sse::is_utf8)
+    LemireSse,
+    /// [`range::sse2::is_utf8`](crate::range::sse2::is_utf8)
+    RangeSse2,
+    /// `range::neon::is_utf8` on AArch64, `range::armv7::is_utf8` on ARM
+    RangeNeon,
+    /// `lemire::neon::is_utf8`, on AArch64
+    LemireNeon,
+    /// `range::simd128::is_utf8`, on WebAssembly
+    RangeSimd128,
+    /// `lemire::simd128::is_utf8`, on WebAssembly
+    LemireSimd128,
+    /// `range::vsx::is_utf8`, on little-endian POWER
+    RangeVsx,
+    /// `lookup::rvv::is_utf8`, on RISC-V with the vector extension
+    LookupRvv,
+    /// `lookup::portable::is_utf8`, with the `portable-simd` feature
+    LookupPortable,
+    /// [`swar::is_utf8`](crate::swar::is_utf8)
+    Swar,
+    /// [`libcore::is_utf8`](crate::libcore::is_utf8)
+    Libcore,
+}
+
+impl Backend {
+    /// All backends, from the fastest to the slowest.
+    pub const ALL: &[Backend] = &[
+        Backend::LookupAvx,
+        // Faster than `LookupAvx` on long runs of ASCII only, and slower on
+        // everything else, so it is not the default.
+        Backend::LookupAvxAsciiPath,
+        Backend::LemireAvxAsciiPath,
+        Backend::LemireAvx,
+        Backend::RangeAvxAsciiPath,
+        Backend::RangeAvx,
+        Backend::LookupSse,
+        Backend::RangeSseAsciiPath,
+        Backend::RangeSse,
+        Backend::LemireSseAsciiPath,
+        Backend::LemireSse,
+        Backend::RangeSse2,
+        Backend::RangeNeon,
+        Backend::LemireNeon,
+        Backend::RangeSimd128,
+        Backend::LemireSimd128,
+        Backend::RangeVsx,
+        Backend::LookupRvv,
+        Backend::LookupPortable,
+        Backend::Swar,
+        Backend::Libcore,
+    ];
+
+    /// Returns the fastest backend this CPU supports.
+    ///
+    /// This is the one [`is_utf8`](crate::is_utf8) uses, unless overridden
+    /// with the `IS_UTF8_BACKEND` environment variable. The exception is the
+    /// NEON backend on 32-bit ARM, which `is_utf8` does not use.
+    #[inline]
+    pub fn fastest() -> Backend {
+        available_backends().next().unwrap_or(Backend::Libcore)
+    }
+
+    /// Returns `true` if this CPU supports the backend.
+    #[inline]
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::LookupAvx
+            | Backend::LookupAvxAsciiPath
+            | Backend::LemireAvxAsciiPath
+            | Backend::LemireAvx
+            | Backend::RangeAvxAsciiPath
+            | Backend::RangeAvx => cpu::has_avx2(),
+            Backend::LookupSse
+            | Backend::RangeSseAsciiPath
+            | Backend::RangeSse
+            | Backend::LemireSseAsciiPath
+            | Backend::LemireSse => cpu::has_sse41(),
+            Backend::RangeSse2 => cpu::has_sse2(),
+            Backend::RangeNeon => cpu::has_neon(),
+            Backend::LemireNeon => cfg!(target_arch = "aarch64") && cpu::has_neon(),
+            Backend::RangeSimd128 | Backend::LemireSimd128 => cpu::has_simd128(),
+            Backend::RangeVsx => cpu::has_vsx(),
+            Backend::LookupRvv => cpu::has_rvv(),
+            Backend::LookupPortable => {
+                cfg!(all(feature = "portable-simd", not(feature = "scalar-only")))
+            }
+            Backend::Swar => !cfg!(feature = "scalar-only"),
+            Backend::Libcore => true,
+        }
+    }
+
+    /// Returns the name of the backend, as accepted by
+    /// [`from_name`](Backend::from_name) and [`str::parse`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::LookupAvx => "lookup_avx",
+            Backend::LookupAvxAsciiPath => "lookup_avx_ascii_path",
+            Backend::LemireAvxAsciiPath => "lemire_avx_ascii_path",
+            Backend::LemireAvx => "lemire_avx",
+            Backend::RangeAvxAsciiPath => "range_avx_ascii_path",
+            Backend::RangeAvx => "range_avx",
+            Backend::LookupSse => "lookup_sse",
+            Backend::RangeSseAsciiPath => "range_sse_ascii_path",
+            Backend::RangeSse => "range_sse",
+            Backend::LemireSseAsciiPath => "lemire_sse_ascii_path",
+            Backend::LemireSse => "lemire_sse",
+            Backend::RangeSse2 => "range_sse2",
+            Backend::RangeNeon => "range_neon",
+            Backend::LemireNeon => "lemire_neon",
+            Backend::RangeSimd128 => "range_simd128",
+            Backend::LemireSimd128 => "lemire_simd128",
+            Backend::RangeVsx => "range_vsx",
+            Backend::LookupRvv => "lookup_rvv",
+            Backend::LookupPortable => "lookup_portable",
+            Backend::Swar => "swar",
+            Backend::Libcore => "libcore",
+        }
+    }
+
+    /// Looks up a backend by its [`name`](Backend::name).
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.iter().copied().find(|b| b.name() == name)
+    }
+
+    /// Returns the backend's `is_utf8` function.
+    ///
+    /// # Safety
+    ///
+    /// The backend must be [available](Backend::is_available).
+    pub(crate) unsafe fn is_utf8_fn(self) -> unsafe fn(&[u8]) -> bool {
+        #[cfg(feature = "portable-simd")]
+        unsafe fn lookup_portable_is_utf8(bytes: &[u8]) -> bool {
+            crate::lookup::portable::is_utf8(bytes)
+        }
+        unsafe fn swar_is_utf8(bytes: &[u8]) -> bool {
+            swar::is_utf8(bytes)
+        }
+        unsafe fn libcore_is_utf8(bytes: &[u8]) -> bool {
+            libcore::is_utf8(bytes)
+        }
+        match self {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LookupAvx => crate::lookup::avx::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LookupAvxAsciiPath => crate::lookup::avx::is_utf8_ascii_path,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LemireAvxAsciiPath => crate::lemire::avx::is_utf8_ascii_path,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LemireAvx => crate::lemire::avx::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::RangeAvxAsciiPath => crate::range::avx::is_utf8_ascii_path,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::RangeAvx => crate::range::avx::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LookupSse => crate::lookup::sse::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::RangeSseAsciiPath => crate::range::sse::is_utf8_ascii_path,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::RangeSse => crate::range::sse::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LemireSseAsciiPath => crate::lemire::sse::is_utf8_ascii_path,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::LemireSse => crate::lemire::sse::is_utf8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::RangeSse2 => crate::range::sse2::is_utf8,
+            #[cfg(target_arch = "aarch64")]
+            Backend::RangeNeon => crate::range::neon::is_utf8,
+            #[cfg(all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"))]
+            Backend::RangeNeon => crate::range::armv7::is_utf8,
+            #[cfg(target_arch = "aarch64")]
+            Backend::LemireNeon => crate::lemire::neon::is_utf8,
+            #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+            Backend::RangeSimd128 => crate::range::simd128::is_utf8,
+            #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+            Backend::LemireSimd128 => crate::lemire::simd128::is_utf8,
+            #[cfg(all(
+                feature = "powerpc-vsx",
+                target_arch = "powerpc64",
+                target_endian = "little",
+                target_feature = "vsx"
+            ))]
+            Backend::RangeVsx => crate::range::vsx::is_utf8,
+            #[cfg(all(target_arch = "riscv64", target_feature = "v"))]
+            Backend::LookupRvv => crate::lookup::rvv::is_utf8,
+            #[cfg(feature = "portable-simd")]
+            Backend::LookupPortable => lookup_portable_is_utf8,
+            Backend::Swar => swar_is_utf8,
+            Backend::Libcore => libcore_is_utf8,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("{} is not available", self.name()),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ParseBackendError;
+
+    /// Looks up a backend by its [`name`](Backend::name), like
+    /// [`from_name`](Backend::from_name).
+    #[inline]
+    fn from_str(name: &str) -> Result<Backend, ParseBackendError> {
+        Backend::from_name(name).ok_or(ParseBackendError(()))
+    }
+}
+
+/// Returns the backends this CPU supports, from the fastest to the slowest.
+///
+/// [`Backend::Libcore`] is always available.
+pub fn available_backends() -> impl Iterator<Item = Backend> {
+    Backend::ALL.iter().copied().filter(|b| b.is_available())
+}
+
+/// Returns `true` if `bytes` is valid UTF-8, checked with the given backend.
+///
+/// # Panics
+///
+/// Panics if the backend is not [available](Backend::is_available).
+pub fn is_utf8_with(backend: Backend, bytes: &[u8]) -> bool {
+    assert!(
+        backend.is_available(),
+        "{} is not supported by this CPU",
+        backend.name()
+    );
+    // The backend was just checked to be available.
+    unsafe { backend.is_utf8_fn()(bytes) }
+}
+
+/// Checks whether `bytes` is valid UTF-8 with the given backend, and if not,
+/// where the first error is.
+///
+/// The error is located with [`libcore::validate`], so it is exactly the one
+/// [`core::str::from_utf8`] would report.
+///
+/// # Panics
+///
+/// Panics if the backend is not [available](Backend::is_available).
+pub fn validate_with(backend: Backend, bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8_with(backend, bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
+
+/// A common interface to the UTF-8 validation algorithms.
+///
+/// The implementors pick the fastest variant of their algorithm that the CPU
+/// supports. Those that need SIMD instructions can only be constructed if
+/// the CPU supports them.
+pub trait Validator {
+    /// Returns `true` if `bytes` is valid UTF-8.
+    fn is_utf8(&self, bytes: &[u8]) -> bool;
+
+    /// Checks whether `bytes` is valid UTF-8, and if not, where the first
+    /// error is.
+    ///
+    /// The error is exactly the one [`core::str::from_utf8`] would report.
+    fn validate(&self, bytes: &[u8]) -> Result<(), Utf8Error> {
+        if self.is_utf8(bytes) {
+            Ok(())
+        } else {
+            libcore::validate(bytes)
+        }
+    }
+}
+
+// Runs the first of `backends` that the CPU supports. The validators store
+// nothing, so they look their backend up on each call, which only reads the
+// cached CPU features.
+#[inline]
+fn run_first_available(backends: &[Backend], bytes: &[u8]) -> bool {
+    for &backend in backends {
+        if backend.is_available() {
+            // `backend` was just checked.
+            return unsafe { backend.is_utf8_fn()(bytes) };
+        }
+    }
+    unreachable!("the validator was constructed without an available backend")
+}
+
+// The backends of each validator, from the fastest to the slowest.
+const LEMIRE: &[Backend] = &[
+    Backend::LemireAvx,
+    Backend::LemireSse,
+    Backend::LemireNeon,
+    Backend::LemireSimd128,
+];
+const LEMIRE_ASCII_PATH: &[Backend] = &[Backend::LemireAvxAsciiPath, Backend::LemireSseAsciiPath];
+const LOOKUP: &[Backend] = &[
+    Backend::LookupAvx,
+    Backend::LookupSse,
+    Backend::LookupRvv,
+    Backend::LookupPortable,
+];
+const LOOKUP_ASCII_PATH: &[Backend] = &[Backend::LookupAvxAsciiPath];
+const RANGE: &[Backend] = &[
+    Backend::RangeAvx,
+    Backend::RangeSse,
+    Backend::RangeSse2,
+    Backend::RangeNeon,
+    Backend::RangeSimd128,
+    Backend::RangeVsx,
+];
+
+/// Daniel Lemire's algorithm, see [`lemire`](crate::lemire).
+#[derive(Copy, Clone, Debug)]
+pub struct Lemire(());
+
+impl Lemire {
+    /// Returns the validator if the CPU supports SSE4.1, AVX2, NEON or
+    /// WebAssembly SIMD.
+    #[inline]
+    pub fn new() -> Option<Self> {
+        if LEMIRE.iter().any(|b| b.is_available()) {
+            Some(Lemire(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Validator for Lemire {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        run_first_available(LEMIRE, bytes)
+    }
+}
+
+/// Daniel Lemire's algorithm with a fast path for ASCII, see
+/// [`lemire::avx::is_utf8_ascii_path`](crate::lemire::avx::is_utf8_ascii_path).
+#[derive(Copy, Clone, Debug)]
+pub struct LemireAsciiPath(());
+
+impl LemireAsciiPath {
+    /// Returns the validator if the CPU supports SSE4.1 or AVX2.
+    #[inline]
+    pub fn new() -> Option<Self> {
+        if LEMIRE_ASCII_PATH.iter().any(|b| b.is_available()) {
+            Some(LemireAsciiPath(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Validator for LemireAsciiPath {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        run_first_available(LEMIRE_ASCII_PATH, bytes)
+    }
+}
+
+/// John Keiser and Daniel Lemire's lookup algorithm, see
+/// [`lookup`](crate::lookup).
+#[derive(Copy, Clone, Debug)]
+pub struct Lookup(());
+
+impl Lookup {
+    /// Returns the validator if the CPU supports SSE4.1, AVX2 or the RISC-V
+    /// vector extension, or if the `portable-simd` feature is enabled.
+    #[inline]
+    pub fn new() -> Option<Self> {
+        if LOOKUP.iter().any(|b| b.is_available()) {
+            Some(Lookup(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Validator for Lookup {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        run_first_available(LOOKUP, bytes)
+    }
+}
+
+/// The range algorithm, see [`range`](crate::range).
+#[derive(Copy, Clone, Debug)]
+pub struct Range(());
+
+impl Range {
+    /// Returns the validator if the CPU supports SSE2, NEON, VSX or
+    /// WebAssembly SIMD.
+    #[inline]
+    pub fn new() -> Option<Self> {
+        if RANGE.iter().any(|b| b.is_available()) {
+            Some(Range(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Validator for Range {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        run_first_available(RANGE, bytes)
+    }
+}
+
+/// The lookup algorithm with a fast path for ASCII, see
+/// [`lookup::avx::is_utf8_ascii_path`](crate::lookup::avx::is_utf8_ascii_path).
+///
+/// Once a few 64-byte blocks in a row were ASCII, it skips ahead 128 bytes at
+/// a time while the input stays ASCII. This suits documents with long runs of
+/// ASCII, such as markup, which it checks up to twice as fast as [`Lookup`].
+/// On other input it is a few percent slower.
+#[derive(Copy, Clone, Debug)]
+pub struct LookupAsciiPath(());
+
+impl LookupAsciiPath {
+    /// Returns the validator if the CPU supports AVX2.
+    #[inline]
+    pub fn new() -> Option<Self> {
+        if LOOKUP_ASCII_PATH.iter().any(|b| b.is_available()) {
+            Some(LookupAsciiPath(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Validator for LookupAsciiPath {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        run_first_available(LOOKUP_ASCII_PATH, bytes)
+    }
+}
+
+/// The portable SWAR algorithm, see [`swar`](crate::swar).
+///
+/// It is available on every CPU.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Swar;
+
+impl Validator for Swar {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        swar::is_utf8(bytes)
+    }
+}
+
+/// The scalar algorithm from Rust's libcore, see [`libcore`](crate::libcore).
+///
+/// It is available on every CPU.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Libcore;
+
+impl Validator for Libcore {
+    #[inline]
+    fn is_utf8(&self, bytes: &[u8]) -> bool {
+        libcore::is_utf8(bytes)
+    }
+
+    #[inline]
+    fn validate(&self, bytes: &[u8]) -> Result<(), Utf8Error> {
+        libcore::validate(bytes)
+    }
+}
@@ -0,0 +1,134 @@
+//! Iteration over the valid and invalid parts of a byte slice.
+use crate::{from_utf8_unchecked, is_utf8, libcore, Utf8Error};
+use core::iter::FusedIterator;
+
+// Number of bytes checked with the scalar validator before switching to
+// `is_utf8`, so that errors close to each other stay cheap to find.
+const SCALAR_LEN: usize = 64;
+// Bounds on the number of bytes checked with `is_utf8` at a time. The window
+// doubles every time it is found valid.
+const MIN_WINDOW: usize = 256;
+const MAX_WINDOW: usize = 64 * 1024;
+
+/// Finds the first error in `v`.
+///
+/// Unlike [`validate`](crate::validate), which scans the whole input with
+/// the SIMD backend before locating the error, this only scans a bit past the
+/// error, so iterating over many errors stays linear.
+fn find_error(v: &[u8]) -> Result<(), Utf8Error> {
+    let head = v.len().min(SCALAR_LEN);
+    let mut start = match libcore::validate(&v[..head]) {
+        Ok(()) => head,
+        Err(e) if e.error_len.is_some() || head == v.len() => return Err(e),
+        // The scalar check ended in the middle of a character.
+        Err(e) => e.valid_up_to,
+    };
+    let mut window = MIN_WINDOW;
+    loop {
+        if v.len() - start <= window {
+            if is_utf8(&v[start..]) {
+                return Ok(());
+            }
+            break;
+        }
+        // Only cut the input at the start of a character.
+        let mut end = start + window;
+        while end > start + window - 3 && v[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        if !is_utf8(&v[start..end]) {
+            break;
+        }
+        start = end;
+        window = (window * 2).min(MAX_WINDOW);
+    }
+    // The error is in the window that `is_utf8` rejected, or just after it,
+    // so the scalar validator stops soon after `start`.
+    libcore::validate(&v[start..]).map_err(|e| Utf8Error {
+        valid_up_to: start + e.valid_up_to,
+        error_len: e.error_len,
+    })
+}
+
+/// A piece of a byte slice, made of a run of valid UTF-8 followed by an
+/// invalid byte sequence.
+///
+/// See [`utf8_chunks`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Utf8Chunk<'a> {
+    valid: &'a str,
+    invalid: &'a [u8],
+}
+
+impl<'a> Utf8Chunk<'a> {
+    /// Returns the valid part of the chunk, which may be empty.
+    #[inline]
+    pub fn valid(&self) -> &'a str {
+        self.valid
+    }
+
+    /// Returns the invalid sequence that follows the valid part.
+    ///
+    /// This is empty only for the last chunk. Otherwise it is a maximal
+    /// invalid subpart of 1 to 3 bytes, or, if the input ends in the middle
+    /// of a multi-byte sequence, the unfinished sequence.
+    #[inline]
+    pub fn invalid(&self) -> &'a [u8] {
+        self.invalid
+    }
+}
+
+/// An iterator over the [`Utf8Chunk`]s of a byte slice.
+///
+/// See [`utf8_chunks`].
+#[derive(Clone, Debug)]
+pub struct Utf8Chunks<'a> {
+    source: &'a [u8],
+}
+
+impl<'a> Iterator for Utf8Chunks<'a> {
+    type Item = Utf8Chunk<'a>;
+
+    fn next(&mut self) -> Option<Utf8Chunk<'a>> {
+        if self.source.is_empty() {
+            return None;
+        }
+        let (valid_len, invalid_len) = match find_error(self.source) {
+            Ok(()) => (self.source.len(), 0),
+            Err(e) => (
+                e.valid_up_to,
+                e.error_len().unwrap_or(self.source.len() - e.valid_up_to),
+            ),
+        };
+        let (valid, rest) = self.source.split_at(valid_len);
+        let (invalid, rest) = rest.split_at(invalid_len);
+        self.source = rest;
+        Some(Utf8Chunk {
+            // `valid` is everything up to the error.
+            valid: unsafe { from_utf8_unchecked(valid) },
+            invalid,
+        })
+    }
+}
+
+impl FusedIterator for Utf8Chunks<'_> {}
+
+/// Splits a byte slice into runs of valid UTF-8, each followed by an invalid
+/// byte sequence.
+///
+/// This works like [`core::str::Utf8Chunks`], but skips over long valid runs
+/// with [`is_utf8`], and only falls back to the scalar validator close to
+/// errors.
+///
+/// ```
+/// let mut chunks = is_utf8::utf8_chunks(b"ab\xFFcd\xE2\x82");
+/// let chunk = chunks.next().unwrap();
+/// assert_eq!((chunk.valid(), chunk.invalid()), ("ab", &b"\xFF"[..]));
+/// let chunk = chunks.next().unwrap();
+/// assert_eq!((chunk.valid(), chunk.invalid()), ("cd", &b"\xE2\x82"[..]));
+/// assert!(chunks.next().is_none());
+/// ```
+#[inline]
+pub fn utf8_chunks(v: &[u8]) -> Utf8Chunks<'_> {
+    Utf8Chunks { source: v }
+}
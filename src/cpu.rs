@@ -0,0 +1,208 @@
+//! Detection of the CPU features the SIMD backends need.
+//!
+//! With the `std` feature, the CPU is queried with
+//! `is_x86_feature_detected!` or `is_aarch64_feature_detected!`. Without it,
+//! x86 CPUs are queried with the `CPUID` and `XGETBV` instructions directly,
+//! and the result is cached in an atomic. Features enabled at compile time are
+//! always reported, and none are with the `scalar-only` feature.
+
+/// Returns `true` if the CPU supports AVX and AVX2.
+#[inline]
+pub(crate) fn has_avx2() -> bool {
+    if cfg!(feature = "scalar-only") {
+        return false;
+    }
+    if cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "avx",
+        target_feature = "avx2"
+    )) {
+        return true;
+    }
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    return std::is_x86_feature_detected!("avx") && std::is_x86_feature_detected!("avx2");
+    #[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+    return x86::features() & x86::AVX2 != 0;
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Returns `true` if the CPU supports SSE2, SSSE3 and SSE4.1.
+#[inline]
+pub(crate) fn has_sse41() -> bool {
+    if cfg!(feature = "scalar-only") {
+        return false;
+    }
+    if cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2",
+        target_feature = "ssse3",
+        target_feature = "sse4.1"
+    )) {
+        return true;
+    }
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    return std::is_x86_feature_detected!("sse2")
+        && std::is_x86_feature_detected!("ssse3")
+        && std::is_x86_feature_detected!("sse4.1");
+    #[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+    return x86::features() & x86::SSE41 != 0;
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Returns `true` if the CPU supports SSE2.
+///
+/// It always does on x86-64.
+#[inline]
+pub(crate) fn has_sse2() -> bool {
+    if cfg!(feature = "scalar-only") {
+        return false;
+    }
+    if cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    )) {
+        return true;
+    }
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    return std::is_x86_feature_detected!("sse2");
+    #[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+    return x86::features() & x86::SSE2 != 0;
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Returns `true` if the CPU supports NEON.
+#[inline]
+pub(crate) fn has_neon() -> bool {
+    if cfg!(feature = "scalar-only") {
+        return false;
+    }
+    if cfg!(any(
+        all(target_arch = "aarch64", target_feature = "neon"),
+        all(
+            feature = "armv7-neon",
+            target_arch = "arm",
+            target_feature = "neon"
+        )
+    )) {
+        return true;
+    }
+    #[cfg(all(feature = "std", target_arch = "aarch64"))]
+    return std::arch::is_aarch64_feature_detected!("neon");
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Returns `true` if WebAssembly SIMD is enabled.
+///
+/// It can only be enabled at compile time.
+#[inline]
+pub(crate) fn has_simd128() -> bool {
+    cfg!(all(
+        not(feature = "scalar-only"),
+        target_arch = "wasm32",
+        target_feature = "simd128"
+    ))
+}
+
+/// Returns `true` if the VSX backend is enabled.
+///
+/// It can only be enabled at compile time.
+#[inline]
+pub(crate) fn has_vsx() -> bool {
+    cfg!(all(
+        not(feature = "scalar-only"),
+        feature = "powerpc-vsx",
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_feature = "vsx"
+    ))
+}
+
+/// Returns `true` if the RISC-V vector extension is enabled.
+///
+/// It can only be enabled at compile time.
+#[inline]
+pub(crate) fn has_rvv() -> bool {
+    cfg!(all(
+        not(feature = "scalar-only"),
+        target_arch = "riscv64",
+        target_feature = "v"
+    ))
+}
+
+#[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    // Bits of `FEATURES`.
+    const INITIALIZED: u8 = 1 << 0;
+    pub(super) const SSE41: u8 = 1 << 1;
+    pub(super) const AVX2: u8 = 1 << 2;
+    pub(super) const SSE2: u8 = 1 << 3;
+
+    static FEATURES: AtomicU8 = AtomicU8::new(0);
+
+    /// Returns the detected features, querying the CPU on the first call.
+    #[inline]
+    pub(super) fn features() -> u8 {
+        let features = FEATURES.load(Ordering::Relaxed);
+        if features & INITIALIZED != 0 {
+            return features;
+        }
+        // Racing threads all store the same value.
+        let features = unsafe { detect() } | INITIALIZED;
+        FEATURES.store(features, Ordering::Relaxed);
+        features
+    }
+
+    #[cold]
+    unsafe fn detect() -> u8 {
+        #[cfg(target_arch = "x86")]
+        {
+            if !has_cpuid() {
+                return 0;
+            }
+        }
+        let max_leaf = __cpuid(0).eax;
+        if max_leaf < 1 {
+            return 0;
+        }
+        let leaf1 = __cpuid(1);
+        let sse2 = leaf1.edx & (1 << 26) != 0;
+        let ssse3 = leaf1.ecx & (1 << 9) != 0;
+        let sse41 = leaf1.ecx & (1 << 19) != 0;
+        let osxsave = leaf1.ecx & (1 << 27) != 0;
+        let avx = leaf1.ecx & (1 << 28) != 0;
+
+        let mut features = 0;
+        if sse2 {
+            features |= SSE2;
+        }
+        if sse2 && ssse3 && sse41 {
+            features |= SSE41;
+        }
+        // AVX registers can only be used if the OS saves and restores both the
+        // XMM and YMM state on context switches, which it reports in XCR0.
+        if osxsave && avx && max_leaf >= 7 {
+            let os_avx = xgetbv0() & 0b110 == 0b110;
+            let avx2 = __cpuid_count(7, 0).ebx & (1 << 5) != 0;
+            if os_avx && avx2 {
+                features |= AVX2;
+            }
+        }
+        features
+    }
+
+    // Only to be called if CPUID reports OSXSAVE.
+    #[target_feature(enable = "xsave")]
+    unsafe fn xgetbv0() -> u64 {
+        _xgetbv(0)
+    }
+}
@@ -0,0 +1,113 @@
+//! Runtime selection of the fastest available implementation.
+//!
+//! On x86, x86-64 and AArch64, the backend is chosen the first time
+//! [`is_utf8`](crate::is_utf8) is called and cached in a function pointer.
+//! With the `std` feature enabled, the `IS_UTF8_BACKEND` environment variable
+//! can be set to the [name](crate::Backend::name) of a backend to force it.
+//! If it names no backend, or one the CPU does not support, the fastest one
+//! is used as if it were not set.
+//!
+//! On little-endian POWER, the VSX backend is chosen at compile time if the
+//! `powerpc-vsx` feature and the `vsx` target feature are enabled. So is the
+//! vector backend on RISC-V if the `v` target feature is, and the SIMD
+//! backend on WebAssembly if the `simd128` target feature is. Everywhere else
+//! the `core::simd` backend is used with the `portable-simd` feature, the
+//! [SWAR implementation](crate::swar) without it, or the scalar one with the
+//! `scalar-only` feature. This includes ARM: the NEON backend of the
+//! `armv7-neon` feature has not been run in CI yet, so it is only used when
+//! asked for, through `range::armv7` or the [`Range`](crate::Range) validator.
+//!
+//! Inputs shorter than [`small::MAX_LEN`] bytes skip all of this, unless the
+//! `scalar-only` feature is enabled, and are checked with a scalar path that
+//! has nothing to set up.
+use crate::small;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+mod imp {
+    use crate::Backend;
+    use core::mem;
+    use core::sync::atomic::{AtomicPtr, Ordering};
+
+    type IsUtf8Fn = unsafe fn(&[u8]) -> bool;
+
+    static IS_UTF8: AtomicPtr<()> = AtomicPtr::new(detect as *mut ());
+
+    // Environment variable that forces a backend.
+    #[cfg(feature = "std")]
+    const BACKEND_VAR: &str = "IS_UTF8_BACKEND";
+
+    #[cfg(feature = "std")]
+    fn select() -> Backend {
+        // A bad value must not make every call fail, so it is ignored. It can
+        // be checked by parsing it as a `Backend`.
+        std::env::var(BACKEND_VAR)
+            .ok()
+            .and_then(|name| name.parse::<Backend>().ok())
+            .filter(|backend| backend.is_available())
+            .unwrap_or_else(Backend::fastest)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn select() -> Backend {
+        Backend::fastest()
+    }
+
+    // Picks an implementation, caches it and forwards the call to it.
+    unsafe fn detect(bytes: &[u8]) -> bool {
+        // `select` only returns available backends.
+        let f = select().is_utf8_fn();
+        IS_UTF8.store(f as *mut (), Ordering::Relaxed);
+        f(bytes)
+    }
+
+    #[inline]
+    pub fn is_utf8(bytes: &[u8]) -> bool {
+        let f = IS_UTF8.load(Ordering::Relaxed);
+        // `IS_UTF8` only ever holds `detect` or the function of an available
+        // backend.
+        unsafe { mem::transmute::<*mut (), IsUtf8Fn>(f)(bytes) }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    // Each target compiles at most one of the vector backends in, and the
+    // fallbacks after it are left unreachable.
+    #[inline]
+    #[allow(unreachable_code)]
+    pub fn is_utf8(bytes: &[u8]) -> bool {
+        #[cfg(all(
+            not(feature = "scalar-only"),
+            feature = "powerpc-vsx",
+            target_arch = "powerpc64",
+            target_endian = "little",
+            target_feature = "vsx"
+        ))]
+        return crate::range::vsx::is_utf8(bytes);
+        #[cfg(all(
+            not(feature = "scalar-only"),
+            target_arch = "riscv64",
+            target_feature = "v"
+        ))]
+        return crate::lookup::rvv::is_utf8(bytes);
+        #[cfg(all(
+            not(feature = "scalar-only"),
+            target_arch = "wasm32",
+            target_feature = "simd128"
+        ))]
+        return crate::range::simd128::is_utf8(bytes);
+        #[cfg(all(not(feature = "scalar-only"), feature = "portable-simd"))]
+        return crate::lookup::portable::is_utf8(bytes);
+        #[cfg(not(feature = "scalar-only"))]
+        return crate::swar::is_utf8(bytes);
+        crate::libcore::is_utf8(bytes)
+    }
+}
+
+#[inline]
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if !cfg!(feature = "scalar-only") && bytes.len() < small::MAX_LEN {
+        return small::is_utf8(bytes);
+    }
+    imp::is_utf8(bytes)
+}
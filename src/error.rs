@@ -0,0 +1,78 @@
+//! Error types.
+use core::fmt;
+
+/// Errors which can occur when attempting to interpret a sequence of [`u8`]
+/// as a string.
+///
+/// This mirrors [`core::str::Utf8Error`], and its accessors return exactly
+/// the same values for the same input.
+#[derive(Copy, Eq, PartialEq, Clone, Debug)]
+pub struct Utf8Error {
+    pub(crate) valid_up_to: usize,
+    pub(crate) error_len: Option<u8>,
+}
+
+impl Utf8Error {
+    /// Returns the index in the given string up to which valid UTF-8 was
+    /// verified.
+    ///
+    /// It is the maximum index such that `from_utf8(&input[..index])` would
+    /// return `Ok(_)`.
+    #[inline]
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+
+    /// Provides more information about the failure:
+    ///
+    /// * `None`: the end of the input was reached unexpectedly.
+    ///   `self.valid_up_to()` is 1 to 3 bytes from the end of the input.
+    ///   If a byte stream (such as a file or a network socket) is being
+    ///   decoded incrementally, this could be a valid `char` whose UTF-8 byte
+    ///   sequence is spanning multiple chunks.
+    ///
+    /// * `Some(len)`: an unexpected byte was encountered. The length provided
+    ///   is that of the invalid byte sequence that starts at the index given
+    ///   by `valid_up_to()`. Decoding should resume after that sequence
+    ///   (after inserting a U+FFFD REPLACEMENT CHARACTER) in case of lossy
+    ///   decoding.
+    #[inline]
+    pub fn error_len(&self) -> Option<usize> {
+        self.error_len.map(|len| len as usize)
+    }
+}
+
+impl fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(error_len) = self.error_len {
+            write!(
+                f,
+                "invalid utf-8 sequence of {} bytes from index {}",
+                error_len, self.valid_up_to
+            )
+        } else {
+            write!(
+                f,
+                "incomplete utf-8 byte sequence from index {}",
+                self.valid_up_to
+            )
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Utf8Error {}
+
+/// The error returned when parsing a [`Backend`](crate::Backend) from a string
+/// that is not the name of one.
+#[derive(Copy, Eq, PartialEq, Clone, Debug)]
+pub struct ParseBackendError(pub(crate) ());
+
+impl fmt::Display for ParseBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unknown UTF-8 validation backend")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseBackendError {}
@@ -36,26 +36,31 @@
  * U+100000..U+10FFFF F4       80..8F   80..BF   80..BF
  *
  */
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
 #[cfg(target_arch = "x86")]
 use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 use core::default::Default;
-use core::ptr;
 
 #[derive(Copy, Clone)]
 struct ProcessedUtfBytes {
     rawbytes: __m256i,
     high_nibbles: __m256i,
+    initial_lengths: __m256i,
+    partial_carries: __m256i,
     carried_continuations: __m256i,
 }
 
 impl Default for ProcessedUtfBytes {
+    #[inline(always)]
     fn default() -> Self {
         unsafe {
             ProcessedUtfBytes {
                 rawbytes: _mm256_setzero_si256(),
                 high_nibbles: _mm256_setzero_si256(),
+                initial_lengths: _mm256_setzero_si256(),
+                partial_carries: _mm256_setzero_si256(),
                 carried_continuations: _mm256_setzero_si256(),
             }
         }
@@ -63,7 +68,7 @@ impl Default for ProcessedUtfBytes {
 }
 
 impl ProcessedUtfBytes {
-    #[inline]
+    #[inline(always)]
     fn count_nibbles(&mut self, bytes: __m256i) {
         self.rawbytes = bytes;
         self.high_nibbles =
@@ -78,6 +83,7 @@ struct State {
 }
 
 impl Default for State {
+    #[inline(always)]
     fn default() -> Self {
         State {
             previous: ProcessedUtfBytes::default(),
@@ -87,230 +93,269 @@ impl Default for State {
 }
 
 impl State {
-    // check whether the current bytes are valid UTF-8
+    // return the errors in the current bytes, except for bytes larger than
+    // 0xF4
     // at the end of the function, previous gets updated
-    fn check_bytes(&mut self, current_bytes: __m256i) {
+    #[inline(always)]
+    fn errors(&mut self, current_bytes: __m256i) -> __m256i {
         let mut pb = ProcessedUtfBytes::default();
         pb.count_nibbles(current_bytes);
-        self.check_smaller_than_0xf4(current_bytes);
-        let initial_lengths = continuation_lengths(pb.high_nibbles);
-        pb.carried_continuations =
-            carry_continuations(initial_lengths, self.previous.carried_continuations);
-        self.check_continuations(initial_lengths, pb.carried_continuations);
+        pb.initial_lengths = continuation_lengths(pb.high_nibbles);
+        let (partial_carries, carried_continuations) =
+            carry_continuations(pb.initial_lengths, &self.previous);
+        pb.partial_carries = partial_carries;
+        pb.carried_continuations = carried_continuations;
         let off1_current_bytes = push_last_byte_of_a_to_b(self.previous.rawbytes, pb.rawbytes);
-        self.check_first_continuation_max(current_bytes, off1_current_bytes);
-        self.check_overlong(
-            current_bytes,
-            off1_current_bytes,
-            pb.high_nibbles,
-            self.previous.high_nibbles,
-        );
+        let errors = unsafe {
+            _mm256_or_si256(
+                check_continuations(pb.initial_lengths, pb.carried_continuations),
+                _mm256_or_si256(
+                    check_first_continuation_max(current_bytes, off1_current_bytes),
+                    check_overlong(current_bytes, off1_current_bytes),
+                ),
+            )
+        };
         self.previous = pb;
+        errors
     }
 
     // check whether the current bytes are valid UTF-8
     // at the end of the function, previous gets updated
-    fn check_bytes_ascii_path(&mut self, current_bytes: __m256i) {
-        if no_most_significant_bits(current_bytes) {
-            unsafe {
-                // Fast ascii path
-                self.has_error = _mm256_or_si256(
-                    _mm256_cmpgt_epi8(
-                        self.previous.carried_continuations,
-                        _mm256_setr_epi8(
-                            9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
-                            9, 9, 9, 9, 9, 9, 9, 1,
-                        ),
-                    ),
-                    self.has_error,
-                );
-                return;
-            }
-        }
-        // Slow non-ascii path
-        self.check_bytes(current_bytes);
-    }
-
-    #[inline]
-    fn check_continuations(&mut self, initial_lengths: __m256i, carries: __m256i) {
+    #[inline(always)]
+    fn check_bytes(&mut self, current_bytes: __m256i) {
+        let errors = self.errors(current_bytes);
         unsafe {
-            // overlap || underlap
-            // carry > length && length > 0 || !(carry > length) && !(length > 0)
-            // (carries > length) == (lengths > 0)
-            let overunder = _mm256_cmpeq_epi8(
-                _mm256_cmpgt_epi8(carries, initial_lengths),
-                _mm256_cmpgt_epi8(initial_lengths, _mm256_setzero_si256()),
+            self.has_error = _mm256_or_si256(
+                self.has_error,
+                _mm256_or_si256(check_smaller_than_0xf4(current_bytes), errors),
             );
-            self.has_error = _mm256_or_si256(self.has_error, overunder);
         }
     }
 
-    // when 0xED is found, next byte must be no larger than 0x9F
-    // when 0xF4 is found, next byte must be no larger than 0x8F
-    // next byte must be continuation, ie sign bit is set, so signed < is ok
-    #[inline]
-    fn check_first_continuation_max(
-        &mut self,
-        current_bytes: __m256i,
-        off1_current_bytes: __m256i,
-    ) {
+    // check four blocks of bytes at once
+    // no check waits for the results of the block before, so the checks of
+    // the four blocks overlap, and their errors are merged before they are
+    // added to has_error
+    // the largest byte of the four blocks is all that has to be no larger
+    // than 0xF4
+    #[inline(always)]
+    fn check_128_bytes(&mut self, current_bytes: [__m256i; 4]) {
+        let errors_0 = self.errors(current_bytes[0]);
+        let errors_1 = self.errors(current_bytes[1]);
+        let errors_2 = self.errors(current_bytes[2]);
+        let errors_3 = self.errors(current_bytes[3]);
         unsafe {
-            let mask_ed = _mm256_cmpeq_epi8(off1_current_bytes, _mm256_set1_epi8(0xEDi32 as i8));
-            let mask_f4 = _mm256_cmpeq_epi8(off1_current_bytes, _mm256_set1_epi8(0xF4i32 as i8));
-            let bad_follow_ed = _mm256_and_si256(
-                _mm256_cmpgt_epi8(current_bytes, _mm256_set1_epi8(0x9Fi32 as i8)),
-                mask_ed,
-            );
-            let bad_follow_f4 = _mm256_and_si256(
-                _mm256_cmpgt_epi8(current_bytes, _mm256_set1_epi8(0x8Fi32 as i8)),
-                mask_f4,
+            let max = _mm256_max_epu8(
+                _mm256_max_epu8(current_bytes[0], current_bytes[1]),
+                _mm256_max_epu8(current_bytes[2], current_bytes[3]),
             );
             self.has_error = _mm256_or_si256(
-                self.has_error,
-                _mm256_or_si256(bad_follow_ed, bad_follow_f4),
+                _mm256_or_si256(self.has_error, check_smaller_than_0xf4(max)),
+                _mm256_or_si256(
+                    _mm256_or_si256(errors_0, errors_1),
+                    _mm256_or_si256(errors_2, errors_3),
+                ),
             );
         }
     }
 
-    // map off1_hibits => error condition
-    // hibits     off1    cur
-    // C       => < C2 && true
-    // E       => < E1 && < A0
-    // F       => < F1 && < 90
-    // else      false && false
-    #[inline]
-    fn check_overlong(
-        &mut self,
-        current_bytes: __m256i,
-        off1_current_bytes: __m256i,
-        hibits: __m256i,
-        previous_hibits: __m256i,
-    ) {
-        unsafe {
-            let off1_hibits = push_last_byte_of_a_to_b(previous_hibits, hibits);
-            let initial_mins = _mm256_shuffle_epi8(
-                _mm256_setr_epi8(
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    0xC2i32 as i8,
-                    -128,
-                    0xE1i32 as i8,
-                    0xF1i32 as i8,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    0xC2i32 as i8,
-                    -128,
-                    0xE1i32 as i8,
-                    0xF1i32 as i8,
-                ),
-                off1_hibits,
-            );
-            let initial_under = _mm256_cmpgt_epi8(initial_mins, off1_current_bytes);
-            let second_mins = _mm256_shuffle_epi8(
-                _mm256_setr_epi8(
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    127,
-                    127,
-                    0xA0i32 as i8,
-                    0x90i32 as i8,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    -128,
-                    127,
-                    127,
-                    0xA0i32 as i8,
-                    0x90i32 as i8,
-                ),
-                off1_hibits,
-            );
-            let second_under = _mm256_cmpgt_epi8(second_mins, current_bytes);
-            self.has_error = _mm256_or_si256(
-                self.has_error,
-                _mm256_and_si256(initial_under, second_under),
-            );
+    // check whether the current bytes are valid UTF-8
+    // at the end of the function, previous gets updated
+    #[inline(always)]
+    fn check_bytes_ascii_path(&mut self, current_bytes: __m256i) {
+        if no_most_significant_bits(current_bytes) {
+            // Fast ascii path: nothing is carried over an ASCII block, so it
+            // is only an error if the bytes before it are incomplete, and
+            // the block after it must not see their continuations.
+            self.check_incomplete();
+            self.previous = ProcessedUtfBytes::default();
+            return;
         }
+        // Slow non-ascii path
+        self.check_bytes(current_bytes);
     }
 
-    // all byte values must be no larger than 0xF4
-    #[inline]
-    fn check_smaller_than_0xf4(&mut self, current_bytes: __m256i) {
+    // the last character of previous must be complete
+    #[inline(always)]
+    fn check_incomplete(&mut self) {
         unsafe {
-            // unsigned, saturates to 0 below max
             self.has_error = _mm256_or_si256(
+                _mm256_cmpgt_epi8(
+                    self.previous.carried_continuations,
+                    _mm256_setr_epi8(
+                        9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
+                        9, 9, 9, 9, 9, 9, 1,
+                    ),
+                ),
                 self.has_error,
-                _mm256_subs_epu8(current_bytes, _mm256_set1_epi8(0xF4i32 as i8)),
             );
         }
     }
 
-    #[inline]
-    fn is_erroneous(&mut self) -> bool {
-        unsafe { _mm256_testz_si256(self.has_error, self.has_error) != 0 }
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { _mm256_testz_si256(self.has_error, self.has_error) == 0 }
+    }
+}
+
+#[inline(always)]
+fn check_continuations(initial_lengths: __m256i, carries: __m256i) -> __m256i {
+    unsafe {
+        // overlap || underlap
+        // carry > length && length > 0 || !(carry > length) && !(length > 0)
+        // (carries > length) == (lengths > 0)
+        _mm256_cmpeq_epi8(
+            _mm256_cmpgt_epi8(carries, initial_lengths),
+            _mm256_cmpgt_epi8(initial_lengths, _mm256_setzero_si256()),
+        )
+    }
+}
+
+// when 0xED is found, next byte must be no larger than 0x9F
+// when 0xF4 is found, next byte must be no larger than 0x8F
+// next byte must be continuation, ie sign bit is set, so signed < is ok
+#[inline(always)]
+fn check_first_continuation_max(current_bytes: __m256i, off1_current_bytes: __m256i) -> __m256i {
+    unsafe {
+        let mask_ed = _mm256_cmpeq_epi8(off1_current_bytes, _mm256_set1_epi8(0xEDi32 as i8));
+        let mask_f4 = _mm256_cmpeq_epi8(off1_current_bytes, _mm256_set1_epi8(0xF4i32 as i8));
+        let bad_follow_ed = _mm256_and_si256(
+            _mm256_cmpgt_epi8(current_bytes, _mm256_set1_epi8(0x9Fi32 as i8)),
+            mask_ed,
+        );
+        let bad_follow_f4 = _mm256_and_si256(
+            _mm256_cmpgt_epi8(current_bytes, _mm256_set1_epi8(0x8Fi32 as i8)),
+            mask_f4,
+        );
+        _mm256_or_si256(bad_follow_ed, bad_follow_f4)
+    }
+}
+
+// map off1_hibits => error condition
+// hibits     off1    cur
+// C       => < C2 && true
+// E       => < E1 && < A0
+// F       => < F1 && < 90
+// else      false && false
+#[inline(always)]
+fn check_overlong(current_bytes: __m256i, off1_current_bytes: __m256i) -> __m256i {
+    unsafe {
+        // a shift is cheaper than shifting the high nibbles across lanes
+        let off1_hibits = _mm256_and_si256(
+            _mm256_srli_epi16(off1_current_bytes, 4),
+            _mm256_set1_epi8(0xF),
+        );
+        let initial_mins = _mm256_shuffle_epi8(
+            _mm256_setr_epi8(
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                0xC2i32 as i8,
+                -128,
+                0xE1i32 as i8,
+                0xF1i32 as i8,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                0xC2i32 as i8,
+                -128,
+                0xE1i32 as i8,
+                0xF1i32 as i8,
+            ),
+            off1_hibits,
+        );
+        let initial_under = _mm256_cmpgt_epi8(initial_mins, off1_current_bytes);
+        let second_mins = _mm256_shuffle_epi8(
+            _mm256_setr_epi8(
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                127,
+                127,
+                0xA0i32 as i8,
+                0x90i32 as i8,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                -128,
+                127,
+                127,
+                0xA0i32 as i8,
+                0x90i32 as i8,
+            ),
+            off1_hibits,
+        );
+        let second_under = _mm256_cmpgt_epi8(second_mins, current_bytes);
+        _mm256_and_si256(initial_under, second_under)
+    }
+}
+
+// all byte values must be no larger than 0xF4
+#[inline(always)]
+fn check_smaller_than_0xf4(current_bytes: __m256i) -> __m256i {
+    unsafe {
+        // unsigned, saturates to 0 below max
+        _mm256_subs_epu8(current_bytes, _mm256_set1_epi8(0xF4i32 as i8))
     }
 }
 
 /// Return `true` if none of the bytes given have their most significant bit
 /// set to `1`.
-#[inline]
+#[inline(always)]
 fn no_most_significant_bits(bytes: __m256i) -> bool {
     unsafe { _mm256_testz_si256(bytes, _mm256_set1_epi8(0x80i32 as i8)) != 0 }
 }
 
-#[inline]
+#[inline(always)]
 fn push_last_byte_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
     unsafe {
         return _mm256_alignr_epi8(b, _mm256_permute2x128_si256(a, b, 0x21), 15);
     }
 }
 
-#[inline]
+#[inline(always)]
 fn push_last_2bytes_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
     unsafe {
         return _mm256_alignr_epi8(b, _mm256_permute2x128_si256(a, b, 0x21), 14);
     }
 }
 
-#[inline]
+#[inline(always)]
 fn continuation_lengths(high_nibbles: __m256i) -> __m256i {
     unsafe {
         return _mm256_shuffle_epi8(
@@ -323,23 +368,39 @@ fn continuation_lengths(high_nibbles: __m256i) -> __m256i {
     }
 }
 
-#[inline]
-fn carry_continuations(initial_lengths: __m256i, previous_carries: __m256i) -> __m256i {
+// the carries are computed from the lengths and partial carries of the
+// previous bytes rather than from their carries, which depend on the bytes
+// before them in turn, so that the checks of consecutive blocks overlap
+#[inline(always)]
+fn carry_continuations(
+    initial_lengths: __m256i,
+    previous: &ProcessedUtfBytes,
+) -> (__m256i, __m256i) {
     unsafe {
         let right1 = _mm256_subs_epu8(
-            push_last_byte_of_a_to_b(previous_carries, initial_lengths),
+            push_last_byte_of_a_to_b(previous.initial_lengths, initial_lengths),
             _mm256_set1_epi8(1),
         );
         let sum = _mm256_add_epi8(initial_lengths, right1);
         let right2 = _mm256_subs_epu8(
-            push_last_2bytes_of_a_to_b(previous_carries, sum),
+            push_last_2bytes_of_a_to_b(previous.partial_carries, sum),
             _mm256_set1_epi8(2),
         );
-        return _mm256_add_epi8(sum, right2);
+        (sum, _mm256_add_epi8(sum, right2))
     }
 }
 
-pub fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+/// Returns `true` if `bytes` is valid UTF-8, skipping the full check for
+/// blocks that only contain ASCII.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
     let len = bytes.len();
     let mut i = 0;
 
@@ -347,44 +408,38 @@ pub fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
 
     if len >= 32 {
         while i <= len - 32 {
-            let current_bytes =
-                unsafe { _mm256_loadu_si256(bytes.as_ptr().offset(i as isize) as *const __m256i) };
-            state.check_bytes_ascii_path(current_bytes);
-            i += 32;
+            let end = (i + ERROR_CHECK_INTERVAL).min(len - 32 + 1);
+            while i < end {
+                let current_bytes =
+                    unsafe { _mm256_loadu_si256(bytes.as_ptr().add(i) as *const __m256i) };
+                state.check_bytes_ascii_path(current_bytes);
+                i += 32;
+            }
+            if state.has_error() {
+                return false;
+            }
         }
     }
     // last part
     if i < len {
-        unsafe {
-            let mut buffer = [0; 32];
-            ptr::write_bytes(buffer.as_mut_ptr(), 0, 32);
-            ptr::copy_nonoverlapping(
-                bytes.as_ptr().offset(i as isize),
-                buffer.as_mut_ptr(),
-                len - i,
-            );
-            let current_bytes_0 = _mm256_loadu_si256(buffer.as_mut_ptr() as *const __m256i);
-            state.check_bytes(current_bytes_0);
-        }
+        state.check_bytes(tail::avx(bytes, i));
     } else {
-        unsafe {
-            state.has_error = _mm256_or_si256(
-                _mm256_cmpgt_epi8(
-                    state.previous.carried_continuations,
-                    _mm256_setr_epi8(
-                        9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
-                        9, 9, 9, 9, 9, 9, 1,
-                    ),
-                ),
-                state.has_error,
-            )
-        }
+        state.check_incomplete();
     }
 
-    state.is_erroneous()
+    !state.has_error()
 }
 
-pub fn is_utf8(bytes: &[u8]) -> bool {
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
     let len = bytes.len();
     let mut i = 0;
 
@@ -392,38 +447,57 @@ pub fn is_utf8(bytes: &[u8]) -> bool {
 
     if len >= 32 {
         while i <= len - 32 {
-            let current_bytes =
-                unsafe { _mm256_loadu_si256(bytes.as_ptr().offset(i as isize) as *const __m256i) };
-            state.check_bytes(current_bytes);
-            i += 32
+            let end = (i + ERROR_CHECK_INTERVAL).min(len - 32 + 1);
+            // 128 bytes at a time
+            while i + 96 < end {
+                let current_bytes = unsafe {
+                    let ptr = bytes.as_ptr().add(i) as *const __m256i;
+                    [
+                        _mm256_loadu_si256(ptr),
+                        _mm256_loadu_si256(ptr.add(1)),
+                        _mm256_loadu_si256(ptr.add(2)),
+                        _mm256_loadu_si256(ptr.add(3)),
+                    ]
+                };
+                state.check_128_bytes(current_bytes);
+                i += 128
+            }
+            while i < end {
+                let current_bytes =
+                    unsafe { _mm256_loadu_si256(bytes.as_ptr().add(i) as *const __m256i) };
+                state.check_bytes(current_bytes);
+                i += 32
+            }
+            if state.has_error() {
+                return false;
+            }
         }
     }
     // last part
     if i < len {
-        let mut buffer = [0; 32];
-        unsafe {
-            ptr::copy_nonoverlapping(
-                bytes.as_ptr().offset(i as isize),
-                buffer.as_mut_ptr(),
-                len - i,
-            );
-            let current_bytes_0 = _mm256_loadu_si256(buffer.as_mut_ptr() as *const __m256i);
-            state.check_bytes(current_bytes_0);
-        }
+        state.check_bytes(tail::avx(bytes, i));
     } else {
-        unsafe {
-            state.has_error = _mm256_or_si256(
-                _mm256_cmpgt_epi8(
-                    state.previous.carried_continuations,
-                    _mm256_setr_epi8(
-                        9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
-                        9, 9, 9, 9, 9, 9, 1,
-                    ),
-                ),
-                state.has_error,
-            )
-        }
+        state.check_incomplete();
     }
 
-    state.is_erroneous()
+    !state.has_error()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
 }
@@ -4,23 +4,15 @@
 //!
 //! [1]: https://lemire.me/blog/2018/10/19/validating-utf-8-bytes-using-only-0-45-cycles-per-byte-avx-edition/
 
-#[cfg(any(all(target_feature = "avx", target_feature = "avx2"), dox))]
-#[cfg_attr(dox, doc(cfg(all(target_feature = "avx", target_feature = "avx2"))))]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
 pub mod avx;
-#[cfg(any(
-    all(
-        target_feature = "sse2",
-        target_feature = "ssse3",
-        target_feature = "sse4.1",
-    ),
-    dox
-))]
-#[cfg_attr(
-    dox,
-    doc(cfg(all(
-        target_feature = "sse2",
-        target_feature = "ssse3",
-        target_feature = "sse4.1",
-    )))
-)]
+#[cfg(target_arch = "aarch64")]
+#[cfg_attr(dox, doc(cfg(target_arch = "aarch64")))]
+pub mod neon;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[cfg_attr(dox, doc(cfg(all(target_arch = "wasm32", target_feature = "simd128"))))]
+pub mod simd128;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
 pub mod sse;
@@ -0,0 +1,273 @@
+//! # NEON implementation of Lemire's algorithm
+//!
+//! A port of the SSE implementation. `vqtbl1q_u8` takes the
+//! place of `_mm_shuffle_epi8` and `vextq_u8` that of `_mm_alignr_epi8`.
+//! NEON has unsigned comparisons, so only the overlong check needs signed
+//! ones.
+//!
+//! ## Target specific intrinsics used:
+//! * vaddq_u8
+//! * vandq_u8
+//! * vceqq_u8
+//! * vcgtq_s8
+//! * vcgtq_u8
+//! * vdupq_n_u8
+//! * vextq_u8
+//! * vld1q_s8
+//! * vld1q_u8
+//! * vmaxvq_u8
+//! * vorrq_u8
+//! * vqsubq_u8
+//! * vqtbl1q_s8
+//! * vqtbl1q_u8
+//! * vreinterpretq_s8_u8
+//! * vshrq_n_u8
+
+/*
+ * legal utf-8 byte sequence
+ * http://www.unicode.org/versions/Unicode6.0.0/ch03.pdf - page 94
+ *
+ *  Code Points        1st       2s       3s       4s
+ * U+0000..U+007F     00..7F
+ * U+0080..U+07FF     C2..DF   80..BF
+ * U+0800..U+0FFF     E0       A0..BF   80..BF
+ * U+1000..U+CFFF     E1..EC   80..BF   80..BF
+ * U+D000..U+D7FF     ED       80..9F   80..BF
+ * U+E000..U+FFFF     EE..EF   80..BF   80..BF
+ * U+10000..U+3FFFF   F0       90..BF   80..BF   80..BF
+ * U+40000..U+FFFFF   F1..F3   80..BF   80..BF   80..BF
+ * U+100000..U+10FFFF F4       80..8F   80..BF   80..BF
+ *
+ */
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::arch::aarch64::*;
+use core::default::Default;
+
+static CONTINUATION_LENGTHS: [u8; 16] = [1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 3, 4];
+#[rustfmt::skip]
+static INITIAL_MINS: [i8; 16] = [
+    -128, -128, -128, -128, -128, -128, -128, -128,
+    -128, -128, -128, -128, 0xC2u8 as i8, -128, 0xE1u8 as i8, 0xF1u8 as i8,
+];
+#[rustfmt::skip]
+static SECOND_MINS: [i8; 16] = [
+    -128, -128, -128, -128, -128, -128, -128, -128,
+    -128, -128, -128, -128, 127, 127, 0xA0u8 as i8, 0x90u8 as i8,
+];
+static MAX_CARRIES: [u8; 16] = [9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 1];
+
+#[derive(Copy, Clone)]
+struct ProcessedUtfBytes {
+    rawbytes: uint8x16_t,
+    high_nibbles: uint8x16_t,
+    carried_continuations: uint8x16_t,
+}
+
+impl Default for ProcessedUtfBytes {
+    #[inline(always)]
+    fn default() -> Self {
+        unsafe {
+            ProcessedUtfBytes {
+                rawbytes: vdupq_n_u8(0),
+                high_nibbles: vdupq_n_u8(0),
+                carried_continuations: vdupq_n_u8(0),
+            }
+        }
+    }
+}
+
+impl ProcessedUtfBytes {
+    #[inline(always)]
+    fn count_nibbles(&mut self, bytes: uint8x16_t) {
+        self.rawbytes = bytes;
+        self.high_nibbles = unsafe { vshrq_n_u8(bytes, 4) };
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    previous: ProcessedUtfBytes,
+    has_error: uint8x16_t,
+}
+
+impl Default for State {
+    #[inline(always)]
+    fn default() -> Self {
+        State {
+            previous: ProcessedUtfBytes::default(),
+            has_error: unsafe { vdupq_n_u8(0) },
+        }
+    }
+}
+
+impl State {
+    // check whether the current bytes are valid UTF-8
+    // at the end of the function, previous gets updated
+    #[inline(always)]
+    fn check_bytes(&mut self, current_bytes: uint8x16_t) {
+        let mut pb = ProcessedUtfBytes::default();
+        pb.count_nibbles(current_bytes);
+        self.check_smaller_than_0xf4(current_bytes);
+        let initial_lengths = continuation_lengths(pb.high_nibbles);
+        pb.carried_continuations =
+            carry_continuations(initial_lengths, self.previous.carried_continuations);
+        self.check_continuations(initial_lengths, pb.carried_continuations);
+        let current_bytes_off_by_one =
+            unsafe { vextq_u8(self.previous.rawbytes, pb.rawbytes, 16 - 1) };
+        self.check_first_continuation_max(current_bytes, current_bytes_off_by_one);
+        self.check_overlong(
+            current_bytes,
+            current_bytes_off_by_one,
+            pb.high_nibbles,
+            self.previous.high_nibbles,
+        );
+        self.previous = pb;
+    }
+
+    // all byte values must be no larger than 0xF4
+    #[inline(always)]
+    fn check_smaller_than_0xf4(&mut self, current_bytes: uint8x16_t) {
+        unsafe {
+            // unsigned, saturates to 0 below max
+            self.has_error = vorrq_u8(self.has_error, vqsubq_u8(current_bytes, vdupq_n_u8(0xF4)));
+        }
+    }
+
+    // when 0xED is found, next byte must be no larger than 0x9F
+    // when 0xF4 is found, next byte must be no larger than 0x8F
+    #[inline(always)]
+    fn check_first_continuation_max(
+        &mut self,
+        current_bytes: uint8x16_t,
+        off1_current_bytes: uint8x16_t,
+    ) {
+        unsafe {
+            let mask_ed = vceqq_u8(off1_current_bytes, vdupq_n_u8(0xED));
+            let mask_f4 = vceqq_u8(off1_current_bytes, vdupq_n_u8(0xF4));
+            let bad_follow_ed = vandq_u8(vcgtq_u8(current_bytes, vdupq_n_u8(0x9F)), mask_ed);
+            let bad_follow_f4 = vandq_u8(vcgtq_u8(current_bytes, vdupq_n_u8(0x8F)), mask_f4);
+            self.has_error = vorrq_u8(self.has_error, vorrq_u8(bad_follow_ed, bad_follow_f4));
+        }
+    }
+
+    #[inline(always)]
+    fn check_continuations(&mut self, initial_lengths: uint8x16_t, carries: uint8x16_t) {
+        unsafe {
+            let overunder = vceqq_u8(
+                vcgtq_u8(carries, initial_lengths),
+                vcgtq_u8(initial_lengths, vdupq_n_u8(0)),
+            );
+            self.has_error = vorrq_u8(self.has_error, overunder);
+        }
+    }
+
+    // map off1_hibits => error condition
+    // hibits     off1    cur
+    // C       => < C2 && true
+    // E       => < E1 && < A0
+    // F       => < F1 && < 90
+    // else      false && false
+    #[inline(always)]
+    fn check_overlong(
+        &mut self,
+        current_bytes: uint8x16_t,
+        off1_current_bytes: uint8x16_t,
+        hibits: uint8x16_t,
+        previous_hibits: uint8x16_t,
+    ) {
+        unsafe {
+            let off1_hibits = vextq_u8(previous_hibits, hibits, 16 - 1);
+            let initial_mins = vqtbl1q_s8(vld1q_s8(INITIAL_MINS.as_ptr()), off1_hibits);
+            let initial_under = vcgtq_s8(initial_mins, vreinterpretq_s8_u8(off1_current_bytes));
+            let second_mins = vqtbl1q_s8(vld1q_s8(SECOND_MINS.as_ptr()), off1_hibits);
+            let second_under = vcgtq_s8(second_mins, vreinterpretq_s8_u8(current_bytes));
+            self.has_error = vorrq_u8(self.has_error, vandq_u8(initial_under, second_under));
+        }
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { vmaxvq_u8(self.has_error) != 0 }
+    }
+}
+
+#[inline(always)]
+fn continuation_lengths(high_nibbles: uint8x16_t) -> uint8x16_t {
+    unsafe { vqtbl1q_u8(vld1q_u8(CONTINUATION_LENGTHS.as_ptr()), high_nibbles) }
+}
+
+#[inline(always)]
+fn carry_continuations(initial_lengths: uint8x16_t, previous_carries: uint8x16_t) -> uint8x16_t {
+    unsafe {
+        let right1 = vqsubq_u8(
+            vextq_u8(previous_carries, initial_lengths, 16 - 1),
+            vdupq_n_u8(1),
+        );
+        let sum = vaddq_u8(initial_lengths, right1);
+        let right2 = vqsubq_u8(vextq_u8(previous_carries, sum, 16 - 2), vdupq_n_u8(2));
+        vaddq_u8(sum, right2)
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support NEON.
+#[target_feature(enable = "neon")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let len = bytes.len();
+    let mut i = 0;
+
+    let mut state = State::default();
+
+    while i <= len - 16 {
+        let end = (i + ERROR_CHECK_INTERVAL).min(len - 16 + 1);
+        while i < end {
+            let current_bytes = unsafe { vld1q_u8(bytes.as_ptr().add(i)) };
+            state.check_bytes(current_bytes);
+            i += 16
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    // last part
+    if i < len {
+        state.check_bytes(tail::neon(bytes, i));
+    } else {
+        state.has_error = unsafe {
+            vorrq_u8(
+                vcgtq_u8(
+                    state.previous.carried_continuations,
+                    vld1q_u8(MAX_CARRIES.as_ptr()),
+                ),
+                state.has_error,
+            )
+        };
+    }
+
+    !state.has_error()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support NEON.
+#[target_feature(enable = "neon")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
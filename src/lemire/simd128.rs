@@ -0,0 +1,264 @@
+//! # WebAssembly SIMD implementation of Lemire's algorithm
+//!
+//! A port of the NEON implementation. `u8x16_swizzle` takes the place of
+//! `vqtbl1q_u8` and `u8x16_shuffle` that of `vextq_u8`.
+//!
+//! WebAssembly has no runtime feature detection. This module is only
+//! compiled if the `simd128` target feature is enabled, so its functions are
+//! safe to call.
+//!
+//! ## Target specific intrinsics used:
+//! * i8x16_gt
+//! * u8x16_add
+//! * u8x16_eq
+//! * u8x16_gt
+//! * u8x16_shr
+//! * u8x16_shuffle
+//! * u8x16_splat
+//! * u8x16_sub_sat
+//! * u8x16_swizzle
+//! * v128_and
+//! * v128_any_true
+//! * v128_load
+//! * v128_or
+
+/*
+ * legal utf-8 byte sequence
+ * http://www.unicode.org/versions/Unicode6.0.0/ch03.pdf - page 94
+ *
+ *  Code Points        1st       2s       3s       4s
+ * U+0000..U+007F     00..7F
+ * U+0080..U+07FF     C2..DF   80..BF
+ * U+0800..U+0FFF     E0       A0..BF   80..BF
+ * U+1000..U+CFFF     E1..EC   80..BF   80..BF
+ * U+D000..U+D7FF     ED       80..9F   80..BF
+ * U+E000..U+FFFF     EE..EF   80..BF   80..BF
+ * U+10000..U+3FFFF   F0       90..BF   80..BF   80..BF
+ * U+40000..U+FFFFF   F1..F3   80..BF   80..BF   80..BF
+ * U+100000..U+10FFFF F4       80..8F   80..BF   80..BF
+ *
+ */
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::arch::wasm32::*;
+use core::default::Default;
+
+static CONTINUATION_LENGTHS: [u8; 16] = [1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 3, 4];
+#[rustfmt::skip]
+static INITIAL_MINS: [u8; 16] = [
+    0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+    0x80, 0x80, 0x80, 0x80, 0xC2, 0x80, 0xE1, 0xF1,
+];
+#[rustfmt::skip]
+static SECOND_MINS: [u8; 16] = [
+    0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+    0x80, 0x80, 0x80, 0x80, 0x7F, 0x7F, 0xA0, 0x90,
+];
+static MAX_CARRIES: [u8; 16] = [9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 1];
+
+#[inline(always)]
+fn load_table(table: &[u8; 16]) -> v128 {
+    unsafe { v128_load(table.as_ptr() as *const v128) }
+}
+
+// (previous, current) << 1 byte
+#[inline(always)]
+fn prev1(previous: v128, current: v128) -> v128 {
+    u8x16_shuffle::<15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30>(
+        previous, current,
+    )
+}
+
+// (previous, current) << 2 bytes
+#[inline(always)]
+fn prev2(previous: v128, current: v128) -> v128 {
+    u8x16_shuffle::<14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29>(
+        previous, current,
+    )
+}
+
+#[derive(Copy, Clone)]
+struct ProcessedUtfBytes {
+    rawbytes: v128,
+    high_nibbles: v128,
+    carried_continuations: v128,
+}
+
+impl Default for ProcessedUtfBytes {
+    #[inline(always)]
+    fn default() -> Self {
+        ProcessedUtfBytes {
+            rawbytes: u8x16_splat(0),
+            high_nibbles: u8x16_splat(0),
+            carried_continuations: u8x16_splat(0),
+        }
+    }
+}
+
+impl ProcessedUtfBytes {
+    #[inline(always)]
+    fn count_nibbles(&mut self, bytes: v128) {
+        self.rawbytes = bytes;
+        self.high_nibbles = u8x16_shr(bytes, 4);
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    previous: ProcessedUtfBytes,
+    has_error: v128,
+}
+
+impl Default for State {
+    #[inline(always)]
+    fn default() -> Self {
+        State {
+            previous: ProcessedUtfBytes::default(),
+            has_error: u8x16_splat(0),
+        }
+    }
+}
+
+impl State {
+    // check whether the current bytes are valid UTF-8
+    // at the end of the function, previous gets updated
+    #[inline(always)]
+    fn check_bytes(&mut self, current_bytes: v128) {
+        let mut pb = ProcessedUtfBytes::default();
+        pb.count_nibbles(current_bytes);
+        self.check_smaller_than_0xf4(current_bytes);
+        let initial_lengths = continuation_lengths(pb.high_nibbles);
+        pb.carried_continuations =
+            carry_continuations(initial_lengths, self.previous.carried_continuations);
+        self.check_continuations(initial_lengths, pb.carried_continuations);
+        let current_bytes_off_by_one = prev1(self.previous.rawbytes, pb.rawbytes);
+        self.check_first_continuation_max(current_bytes, current_bytes_off_by_one);
+        self.check_overlong(
+            current_bytes,
+            current_bytes_off_by_one,
+            pb.high_nibbles,
+            self.previous.high_nibbles,
+        );
+        self.previous = pb;
+    }
+
+    // all byte values must be no larger than 0xF4
+    #[inline(always)]
+    fn check_smaller_than_0xf4(&mut self, current_bytes: v128) {
+        // unsigned, saturates to 0 below max
+        self.has_error = v128_or(
+            self.has_error,
+            u8x16_sub_sat(current_bytes, u8x16_splat(0xF4)),
+        );
+    }
+
+    // when 0xED is found, next byte must be no larger than 0x9F
+    // when 0xF4 is found, next byte must be no larger than 0x8F
+    #[inline(always)]
+    fn check_first_continuation_max(&mut self, current_bytes: v128, off1_current_bytes: v128) {
+        let mask_ed = u8x16_eq(off1_current_bytes, u8x16_splat(0xED));
+        let mask_f4 = u8x16_eq(off1_current_bytes, u8x16_splat(0xF4));
+        let bad_follow_ed = v128_and(u8x16_gt(current_bytes, u8x16_splat(0x9F)), mask_ed);
+        let bad_follow_f4 = v128_and(u8x16_gt(current_bytes, u8x16_splat(0x8F)), mask_f4);
+        self.has_error = v128_or(self.has_error, v128_or(bad_follow_ed, bad_follow_f4));
+    }
+
+    #[inline(always)]
+    fn check_continuations(&mut self, initial_lengths: v128, carries: v128) {
+        let overunder = u8x16_eq(
+            u8x16_gt(carries, initial_lengths),
+            u8x16_gt(initial_lengths, u8x16_splat(0)),
+        );
+        self.has_error = v128_or(self.has_error, overunder);
+    }
+
+    // map off1_hibits => error condition
+    // hibits     off1    cur
+    // C       => < C2 && true
+    // E       => < E1 && < A0
+    // F       => < F1 && < 90
+    // else      false && false
+    #[inline(always)]
+    fn check_overlong(
+        &mut self,
+        current_bytes: v128,
+        off1_current_bytes: v128,
+        hibits: v128,
+        previous_hibits: v128,
+    ) {
+        let off1_hibits = prev1(previous_hibits, hibits);
+        let initial_mins = u8x16_swizzle(load_table(&INITIAL_MINS), off1_hibits);
+        let initial_under = i8x16_gt(initial_mins, off1_current_bytes);
+        let second_mins = u8x16_swizzle(load_table(&SECOND_MINS), off1_hibits);
+        let second_under = i8x16_gt(second_mins, current_bytes);
+        self.has_error = v128_or(self.has_error, v128_and(initial_under, second_under));
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        v128_any_true(self.has_error)
+    }
+}
+
+#[inline(always)]
+fn continuation_lengths(high_nibbles: v128) -> v128 {
+    u8x16_swizzle(load_table(&CONTINUATION_LENGTHS), high_nibbles)
+}
+
+#[inline(always)]
+fn carry_continuations(initial_lengths: v128, previous_carries: v128) -> v128 {
+    let right1 = u8x16_sub_sat(prev1(previous_carries, initial_lengths), u8x16_splat(1));
+    let sum = u8x16_add(initial_lengths, right1);
+    let right2 = u8x16_sub_sat(prev2(previous_carries, sum), u8x16_splat(2));
+    u8x16_add(sum, right2)
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let len = bytes.len();
+    let mut i = 0;
+
+    let mut state = State::default();
+
+    while i <= len - 16 {
+        let end = (i + ERROR_CHECK_INTERVAL).min(len - 16 + 1);
+        while i < end {
+            let current_bytes = unsafe { v128_load(bytes.as_ptr().add(i) as *const v128) };
+            state.check_bytes(current_bytes);
+            i += 16
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    // last part
+    if i < len {
+        state.check_bytes(tail::simd128(bytes, i));
+    } else {
+        state.has_error = v128_or(
+            u8x16_gt(
+                state.previous.carried_continuations,
+                load_table(&MAX_CARRIES),
+            ),
+            state.has_error,
+        );
+    }
+
+    !state.has_error()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -38,12 +38,12 @@
  * U+100000..U+10FFFF F4       80..8F   80..BF   80..BF
  *
  */
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
 #[cfg(target_arch = "x86")]
 use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 use core::default::Default;
-use core::ptr;
 
 #[derive(Copy, Clone)]
 struct ProcessedUtfBytes {
@@ -53,6 +53,7 @@ struct ProcessedUtfBytes {
 }
 
 impl Default for ProcessedUtfBytes {
+    #[inline(always)]
     fn default() -> Self {
         unsafe {
             ProcessedUtfBytes {
@@ -65,7 +66,7 @@ impl Default for ProcessedUtfBytes {
 }
 
 impl ProcessedUtfBytes {
-    #[inline]
+    #[inline(always)]
     fn count_nibbles(&mut self, bytes: __m128i) {
         self.rawbytes = bytes;
         self.high_nibbles = unsafe { _mm_and_si128(_mm_srli_epi16(bytes, 4), _mm_set1_epi8(0xF)) };
@@ -79,6 +80,7 @@ struct State {
 }
 
 impl Default for State {
+    #[inline(always)]
     fn default() -> Self {
         State {
             previous: ProcessedUtfBytes::default(),
@@ -90,6 +92,7 @@ impl Default for State {
 impl State {
     // check whether the current bytes are valid UTF-8
     // at the end of the function, previous gets updated
+    #[inline(always)]
     fn check_bytes(&mut self, current_bytes: __m128i) {
         let mut pb = ProcessedUtfBytes::default();
         pb.count_nibbles(current_bytes);
@@ -110,8 +113,38 @@ impl State {
         self.previous = pb;
     }
 
+    // check whether the current bytes are valid UTF-8
+    // at the end of the function, previous gets updated
+    #[inline(always)]
+    fn check_bytes_ascii_path(&mut self, current_bytes: __m128i) {
+        if no_most_significant_bits(current_bytes) {
+            // Fast ascii path: nothing is carried over an ASCII block, so it
+            // is only an error if the bytes before it are incomplete, and
+            // the block after it must not see their continuations.
+            self.check_incomplete();
+            self.previous = ProcessedUtfBytes::default();
+            return;
+        }
+        // Slow non-ascii path
+        self.check_bytes(current_bytes);
+    }
+
+    // the last character of previous must be complete
+    #[inline(always)]
+    fn check_incomplete(&mut self) {
+        unsafe {
+            self.has_error = _mm_or_si128(
+                _mm_cmpgt_epi8(
+                    self.previous.carried_continuations,
+                    _mm_setr_epi8(9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 1),
+                ),
+                self.has_error,
+            );
+        }
+    }
+
     // all byte values must be no larger than 0xF4
-    #[inline]
+    #[inline(always)]
     fn check_smaller_than_0xf4(&mut self, current_bytes: __m128i) {
         unsafe {
             // unsigned, saturates to 0 below max
@@ -125,7 +158,7 @@ impl State {
     // when 0xED is found, next byte must be no larger than 0x9F
     // when 0xF4 is found, next byte must be no larger than 0x8F
     // next byte must be continuation, ie sign bit is set, so signed < is ok
-    #[inline]
+    #[inline(always)]
     fn check_first_continuation_max(
         &mut self,
         current_bytes: __m128i,
@@ -147,7 +180,7 @@ impl State {
         }
     }
 
-    #[inline]
+    #[inline(always)]
     fn check_continuations(&mut self, initial_lengths: __m128i, carries: __m128i) {
         unsafe {
             let overunder = _mm_cmpeq_epi8(
@@ -164,7 +197,7 @@ impl State {
     // E       => < E1 && < A0
     // F       => < F1 && < 90
     // else      false && false
-    #[inline]
+    #[inline(always)]
     fn check_overlong(
         &mut self,
         current_bytes: __m128i,
@@ -223,12 +256,20 @@ impl State {
         }
     }
 
-    fn is_erroneous(self) -> bool {
-        unsafe { _mm_testz_si128(self.has_error, self.has_error) != 0 }
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { _mm_testz_si128(self.has_error, self.has_error) == 0 }
     }
 }
 
-#[inline]
+/// Return `true` if none of the bytes given have their most significant bit
+/// set to `1`.
+#[inline(always)]
+fn no_most_significant_bits(bytes: __m128i) -> bool {
+    unsafe { _mm_testz_si128(bytes, _mm_set1_epi8(0x80i32 as i8)) != 0 }
+}
+
+#[inline(always)]
 fn continuation_lengths(high_nibbles: __m128i) -> __m128i {
     unsafe {
         _mm_shuffle_epi8(
@@ -238,7 +279,7 @@ fn continuation_lengths(high_nibbles: __m128i) -> __m128i {
     }
 }
 
-#[inline]
+#[inline(always)]
 fn carry_continuations(initial_lengths: __m128i, previous_carries: __m128i) -> __m128i {
     unsafe {
         let right1 = _mm_subs_epu8(
@@ -254,43 +295,94 @@ fn carry_continuations(initial_lengths: __m128i, previous_carries: __m128i) -> _
     }
 }
 
-pub fn is_utf8(bytes: &[u8]) -> bool {
+/// Returns `true` if `bytes` is valid UTF-8, skipping the full check for
+/// blocks that only contain ASCII.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
     let len = bytes.len();
     let mut i = 0;
 
     let mut state = State::default();
 
-    if len >= 16 {
-        while i <= len - 16 {
-            let current_bytes =
-                unsafe { _mm_loadu_si128(bytes.as_ptr().offset(i as isize) as *const __m128i) };
-            state.check_bytes(current_bytes);
+    while i <= len - 16 {
+        let end = (i + ERROR_CHECK_INTERVAL).min(len - 16 + 1);
+        while i < end {
+            let current_bytes = unsafe { _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i) };
+            state.check_bytes_ascii_path(current_bytes);
             i += 16
         }
+        if state.has_error() {
+            return false;
+        }
     }
     // last part
     if i < len {
-        let mut buffer = [0; 16];
-        unsafe {
-            ptr::copy_nonoverlapping(
-                bytes.as_ptr().offset(i as isize),
-                buffer.as_mut_ptr(),
-                len - i,
-            );
-            let current_bytes_0 = _mm_loadu_si128(buffer.as_mut_ptr() as *const __m128i);
-            state.check_bytes(current_bytes_0);
-        }
+        state.check_bytes(tail::sse(bytes, i));
     } else {
-        unsafe {
-            state.has_error = _mm_or_si128(
-                _mm_cmpgt_epi8(
-                    state.previous.carried_continuations,
-                    _mm_setr_epi8(9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 1),
-                ),
-                state.has_error,
-            )
+        state.check_incomplete();
+    }
+
+    !state.has_error()
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let len = bytes.len();
+    let mut i = 0;
+
+    let mut state = State::default();
+
+    while i <= len - 16 {
+        let end = (i + ERROR_CHECK_INTERVAL).min(len - 16 + 1);
+        while i < end {
+            let current_bytes = unsafe { _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i) };
+            state.check_bytes(current_bytes);
+            i += 16
         }
+        if state.has_error() {
+            return false;
+        }
+    }
+    // last part
+    if i < len {
+        state.check_bytes(tail::sse(bytes, i));
+    } else {
+        state.check_incomplete();
     }
 
-    state.is_erroneous()
+    !state.has_error()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
 }
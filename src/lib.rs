@@ -5,15 +5,173 @@
 //
 #![no_std]
 #![cfg_attr(dox, feature(doc_cfg))]
+#![cfg_attr(
+    all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"),
+    feature(stdarch_arm_neon_intrinsics)
+)]
+#![cfg_attr(
+    all(
+        feature = "powerpc-vsx",
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_feature = "vsx"
+    ),
+    feature(stdarch_powerpc)
+)]
+#![cfg_attr(feature = "portable-simd", feature(portable_simd))]
 
+mod backend;
+mod chunks;
+mod cpu;
+mod dispatch;
+mod error;
 pub mod lemire;
 pub mod libcore;
+pub mod lookup;
 pub mod range;
+mod small;
+mod stream;
+#[cfg(feature = "alloc")]
+mod string;
+pub mod swar;
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    all(target_arch = "wasm32", target_feature = "simd128"),
+    all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"),
+    all(
+        feature = "powerpc-vsx",
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_feature = "vsx"
+    )
+))]
+mod tail;
 
-#[cfg(test)]
-#[macro_use]
+// Bytes the SIMD backends check between tests of their error register, so
+// that invalid input is rejected without reading all of it. Testing once per
+// interval rather than once per block keeps the inner loops as tight as they
+// are without the test. Targets without a SIMD backend never use it.
+#[cfg_attr(
+    not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128"),
+        all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"),
+        all(
+            feature = "powerpc-vsx",
+            target_arch = "powerpc64",
+            target_endian = "little",
+            target_feature = "vsx"
+        ),
+        feature = "portable-simd"
+    )),
+    allow(dead_code)
+)]
+const ERROR_CHECK_INTERVAL: usize = 4096;
+
+pub use crate::backend::{
+    available_backends, is_utf8_with, validate_with, Backend, Lemire, LemireAsciiPath, Libcore,
+    Lookup, LookupAsciiPath, Range, Swar, Validator,
+};
+pub use crate::chunks::{utf8_chunks, Utf8Chunk, Utf8Chunks};
+pub use crate::error::{ParseBackendError, Utf8Error};
+pub use crate::stream::{validate_prefix, Utf8Prefix, Utf8Validator};
+#[cfg(feature = "alloc")]
+#[cfg_attr(dox, doc(cfg(feature = "alloc")))]
+pub use crate::string::{
+    cow_from_utf8, from_utf8_lossy, from_utf8_lossy_with, into_boxed_str, string_from_utf8,
+    FromUtf8Error, Replacement,
+};
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+#[cfg_attr(test, macro_use)]
 extern crate std;
 
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// This uses the fastest implementation supported by the CPU. On x86 and
+/// x86-64, the CPU is queried at runtime on the first call, and the choice is
+/// cached for subsequent calls. This works without the `std` feature too, by
+/// reading `CPUID` directly. With `std`, setting the `IS_UTF8_BACKEND`
+/// environment variable to the [name](Backend::name) of a backend forces that
+/// one instead, unless the CPU does not support it. A value that is not the
+/// name of a backend is ignored too. With the `scalar-only` feature, the
+/// scalar implementation is always used. Otherwise, inputs shorter than 16
+/// bytes are checked with a scalar path of their own, as vector code costs
+/// more to set up than it saves on them.
+#[inline]
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    dispatch::is_utf8(bytes)
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first, and is only scanned again
+/// with [`libcore::validate`] to locate the error if that fails. The error is
+/// exactly the one [`core::str::from_utf8`] would report.
+#[inline]
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
+
+/// Converts a slice of bytes to a string slice.
+///
+/// This is a drop-in replacement for [`core::str::from_utf8`] that checks the
+/// input with [`is_utf8`].
+#[inline]
+pub fn from_utf8(v: &[u8]) -> Result<&str, Utf8Error> {
+    validate(v)?;
+    // `v` was just validated.
+    Ok(unsafe { from_utf8_unchecked(v) })
+}
+
+/// Converts a mutable slice of bytes to a mutable string slice.
+///
+/// This is a drop-in replacement for [`core::str::from_utf8_mut`] that checks
+/// the input with [`is_utf8`].
+#[inline]
+pub fn from_utf8_mut(v: &mut [u8]) -> Result<&mut str, Utf8Error> {
+    validate(v)?;
+    // `v` was just validated.
+    Ok(unsafe { from_utf8_unchecked_mut(v) })
+}
+
+/// Converts a slice of bytes to a string slice without checking that it
+/// contains valid UTF-8.
+///
+/// See [`core::str::from_utf8_unchecked`].
+///
+/// # Safety
+///
+/// The bytes passed in must be valid UTF-8.
+#[inline]
+pub unsafe fn from_utf8_unchecked(v: &[u8]) -> &str {
+    core::str::from_utf8_unchecked(v)
+}
+
+/// Converts a mutable slice of bytes to a mutable string slice without
+/// checking that it contains valid UTF-8.
+///
+/// See [`core::str::from_utf8_unchecked_mut`].
+///
+/// # Safety
+///
+/// The bytes passed in must be valid UTF-8.
+#[inline]
+pub unsafe fn from_utf8_unchecked_mut(v: &mut [u8]) -> &mut str {
+    core::str::from_utf8_unchecked_mut(v)
+}
+
 #[cfg(test)]
 mod tests {
     static UTF8_SAMPLE_OK: &'static str = include_str!("../props/utf8_sample_ok.txt");
@@ -226,24 +384,631 @@ mod tests {
         };
     }
 
+    macro_rules! create_validate_tests {
+        ($validate:ident) => {
+            let expected = |bytes: &[u8]| {
+                core::str::from_utf8(bytes)
+                    .map(|_| ())
+                    .map_err(|e| (e.valid_up_to(), e.error_len()))
+            };
+            let check = |bytes: &[u8]| {
+                let actual = $validate(bytes).map_err(|e| (e.valid_up_to(), e.error_len()));
+                assert_eq!(actual, expected(bytes));
+            };
+
+            check(b"");
+            check(UTF8_SAMPLE_OK.as_bytes());
+            check(ASCII_SAMPLE_OK.as_bytes());
+            check(MOSTLY_ASCII_SAMPLE_OK.as_bytes());
+            check(ALL_UTF8_CHARACTERS_WITH_GARBAGE);
+            check(RANDOM_BYTES);
+
+            // Place invalid and truncated sequences at every offset of a
+            // multi-block input, so that they hit every lane and block
+            // boundary.
+            let bad: &[&[u8]] = &[
+                &[0x80],
+                &[0xC0, 0x80],
+                &[0xE0, 0x80, 0x80],
+                &[0xED, 0xA0, 0x80],
+                &[0xF4, 0x90, 0x80, 0x80],
+                &[0xFF],
+                &[0xC2],
+                &[0xE2, 0x82],
+                &[0xF0, 0x9F, 0x98],
+            ];
+            let valid = UTF8_SAMPLE_OK.as_bytes();
+            let mut buffer = std::vec::Vec::new();
+            for sequence in bad {
+                for offset in 0..100 {
+                    let mut end = offset;
+                    while !UTF8_SAMPLE_OK.is_char_boundary(end) {
+                        end += 1;
+                    }
+                    buffer.clear();
+                    buffer.extend_from_slice(&valid[..end]);
+                    buffer.extend_from_slice(sequence);
+                    check(&buffer);
+                    buffer.extend_from_slice(&valid[..100]);
+                    check(&buffer);
+                }
+            }
+        };
+    }
+
+    #[test]
+    fn test_is_utf8() {
+        use super::is_utf8;
+        create_tests!(is_utf8);
+    }
+
     #[test]
-    #[cfg(all(target_feature = "avx", target_feature = "avx2"))]
+    fn test_validate() {
+        use super::validate;
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    fn test_from_utf8() {
+        use super::{from_utf8, from_utf8_mut};
+
+        assert_eq!(from_utf8(UTF8_SAMPLE_OK.as_bytes()), Ok(UTF8_SAMPLE_OK));
+        assert_eq!(from_utf8(b""), Ok(""));
+        let err = from_utf8(&[b'a', 0xC0, 0x80]).unwrap_err();
+        assert_eq!((err.valid_up_to(), err.error_len()), (1, Some(1)));
+        let err = from_utf8(&[b'a', 0xE2, 0x82]).unwrap_err();
+        assert_eq!((err.valid_up_to(), err.error_len()), (1, None));
+
+        let mut bytes = std::vec::Vec::from(MOSTLY_ASCII_SAMPLE_OK.as_bytes());
+        let s = from_utf8_mut(&mut bytes).unwrap();
+        s.make_ascii_uppercase();
+        assert_eq!(*s, MOSTLY_ASCII_SAMPLE_OK.to_ascii_uppercase());
+        assert!(from_utf8_mut(&mut [0xFF]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_string_from_utf8() {
+        use super::{cow_from_utf8, into_boxed_str, string_from_utf8};
+        use std::borrow::Cow;
+        use std::string::{String, ToString};
+        use std::vec::Vec;
+
+        let bytes = Vec::from(UTF8_SAMPLE_OK.as_bytes());
+        assert_eq!(string_from_utf8(bytes).unwrap(), UTF8_SAMPLE_OK);
+        let bytes = Vec::from(&b"abc\xE2\x82"[..]);
+        let err = string_from_utf8(bytes.clone()).unwrap_err();
+        assert_eq!(err.utf8_error().valid_up_to(), 3);
+        assert_eq!(err.utf8_error().error_len(), None);
+        assert_eq!(
+            err.to_string(),
+            String::from_utf8(bytes.clone()).unwrap_err().to_string()
+        );
+        assert_eq!(err.into_bytes(), bytes);
+
+        let boxed = Vec::from(ASCII_SAMPLE_OK.as_bytes()).into_boxed_slice();
+        assert_eq!(&*into_boxed_str(boxed).unwrap(), ASCII_SAMPLE_OK);
+        let boxed = Vec::from(&[0xFF][..]).into_boxed_slice();
+        assert_eq!(into_boxed_str(boxed).unwrap_err().as_bytes(), &[0xFF]);
+
+        let borrowed = cow_from_utf8(Cow::Borrowed(MOSTLY_ASCII_SAMPLE_OK.as_bytes()));
+        assert!(matches!(borrowed, Ok(Cow::Borrowed(s)) if s == MOSTLY_ASCII_SAMPLE_OK));
+        let owned = cow_from_utf8(Cow::Owned(Vec::from(&b"abc"[..])));
+        assert!(matches!(owned, Ok(Cow::Owned(ref s)) if s == "abc"));
+        let err = cow_from_utf8(Cow::Borrowed(&[b'a', 0x80][..])).unwrap_err();
+        assert_eq!(err.as_bytes(), &[b'a', 0x80]);
+    }
+
+    #[test]
+    fn test_utf8_validator() {
+        use super::Utf8Validator;
+
+        let streamed = |bytes: &[u8], chunk_size: usize| {
+            let mut validator = Utf8Validator::new();
+            for chunk in bytes.chunks(chunk_size) {
+                validator.update(chunk);
+            }
+            validator
+                .finish()
+                .map_err(|e| (e.valid_up_to(), e.error_len()))
+        };
+        let expected = |bytes: &[u8]| {
+            core::str::from_utf8(bytes)
+                .map(|_| ())
+                .map_err(|e| (e.valid_up_to(), e.error_len()))
+        };
+
+        let valid = UTF8_SAMPLE_OK.as_bytes();
+        let mut inputs = vec![
+            std::vec::Vec::new(),
+            valid.to_vec(),
+            RANDOM_BYTES[..1000].to_vec(),
+        ];
+        for sequence in &[
+            &[0x80][..],
+            &[0xE0, 0x80, 0x80],
+            &[0xF4, 0x90, 0x80, 0x80],
+            &[0xF0, 0x9F, 0x98, 0x41],
+            &[0xF0, 0x9F, 0x98],
+        ] {
+            for &offset in &[0, 3, 31, 64, 1001] {
+                let mut input = valid[..offset].to_vec();
+                input.extend_from_slice(sequence);
+                inputs.push(input.clone());
+                input.extend_from_slice(&valid[..200]);
+                inputs.push(input);
+            }
+        }
+        for input in &inputs {
+            for chunk_size in 1..70 {
+                assert_eq!(streamed(input, chunk_size), expected(input));
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_prefix() {
+        use super::{validate_prefix, Utf8Prefix};
+
+        assert_eq!(validate_prefix(b""), Utf8Prefix::Complete);
+        assert_eq!(
+            validate_prefix(UTF8_SAMPLE_OK.as_bytes()),
+            Utf8Prefix::Complete
+        );
+        let truncated: &[(&[u8], usize)] = &[
+            (&[0xC2], 1),
+            (&[0xE2], 2),
+            (&[0xE2, 0x82], 1),
+            (&[0xF0], 3),
+            (&[0xF0, 0x9F], 2),
+            (&[0xF0, 0x9F, 0x98], 1),
+        ];
+        for &(tail, needed) in truncated {
+            let mut input = UTF8_SAMPLE_OK.as_bytes().to_vec();
+            input.extend_from_slice(tail);
+            assert_eq!(
+                validate_prefix(&input),
+                Utf8Prefix::Incomplete {
+                    valid_up_to: UTF8_SAMPLE_OK.len(),
+                    needed
+                }
+            );
+        }
+        for input in &[&[0xE0, 0x80][..], &[0xC2, 0x41], &[0xF4, 0x90], &[0xFF]] {
+            let err = core::str::from_utf8(input).unwrap_err();
+            match validate_prefix(input) {
+                Utf8Prefix::Invalid(e) => {
+                    assert_eq!(e.valid_up_to(), err.valid_up_to());
+                    assert_eq!(e.error_len(), err.error_len());
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_from_utf8_lossy() {
+        use super::{from_utf8_lossy, from_utf8_lossy_with, Replacement};
+        use std::borrow::Cow;
+        use std::string::String;
+
+        assert!(matches!(
+            from_utf8_lossy(UTF8_SAMPLE_OK.as_bytes()),
+            Cow::Borrowed(s) if s == UTF8_SAMPLE_OK
+        ));
+        assert!(matches!(
+            from_utf8_lossy_with(b"", Replacement::Remove),
+            Cow::Borrowed("")
+        ));
+        assert_eq!(
+            from_utf8_lossy(ALL_UTF8_CHARACTERS_WITH_GARBAGE),
+            String::from_utf8_lossy(ALL_UTF8_CHARACTERS_WITH_GARBAGE)
+        );
+        assert_eq!(
+            from_utf8_lossy(RANDOM_BYTES),
+            String::from_utf8_lossy(RANDOM_BYTES)
+        );
+
+        let inputs: &[&[u8]] = &[
+            b"Hello\xC0\x80 There\xE6\x83 Goodbye",
+            b"\xF5foo\xF5\x80bar",
+            b"\xF1foo\xF1\x80bar\xF1\x80\x80baz",
+            b"\xF4foo\xF4\x80bar\xF4\xBFbaz",
+            b"\xF0\x80\x80\x80foo\xF0\x90\x80\x80bar",
+            b"\xED\xA0\x80foo\xED\xBF\xBFbar",
+            b"\xE2\x82",
+        ];
+        for input in inputs {
+            let std = String::from_utf8_lossy(input);
+            assert_eq!(from_utf8_lossy(input), std);
+            assert_eq!(
+                from_utf8_lossy_with(input, Replacement::Char('?')),
+                std.replace('\u{FFFD}', "?")
+            );
+            assert_eq!(
+                from_utf8_lossy_with(input, Replacement::Remove),
+                std.replace('\u{FFFD}', "")
+            );
+        }
+    }
+
+    #[test]
+    fn test_utf8_chunks() {
+        use super::utf8_chunks;
+        use std::vec::Vec;
+
+        fn expected(bytes: &[u8]) -> Vec<(&str, &[u8])> {
+            bytes
+                .utf8_chunks()
+                .map(|c| (c.valid(), c.invalid()))
+                .collect()
+        }
+        fn actual(bytes: &[u8]) -> Vec<(&str, &[u8])> {
+            utf8_chunks(bytes)
+                .map(|c| (c.valid(), c.invalid()))
+                .collect()
+        }
+
+        assert_eq!(actual(b""), expected(b""));
+        assert_eq!(actual(RANDOM_BYTES), expected(RANDOM_BYTES));
+        assert_eq!(
+            actual(ALL_UTF8_CHARACTERS_WITH_GARBAGE),
+            expected(ALL_UTF8_CHARACTERS_WITH_GARBAGE)
+        );
+        let valid = ALL_UTF8_CHARACTERS.as_bytes();
+        assert_eq!(actual(valid), expected(valid));
+
+        // Errors placed around the window boundaries of the fast path.
+        let mut input = valid[..100_000].to_vec();
+        for &offset in &[63, 64, 65, 319, 320, 321, 831, 832, 833, 1855, 1856, 1857] {
+            for &byte in &[0x80, 0xC2, 0xE2, 0xF0, 0xFF] {
+                let saved = input[offset];
+                input[offset] = byte;
+                assert_eq!(actual(&input), expected(&input));
+                assert_eq!(actual(&input[..offset + 1]), expected(&input[..offset + 1]));
+                input[offset] = saved;
+            }
+        }
+    }
+
+    #[test]
+    fn test_backends() {
+        use super::{available_backends, is_utf8_with, validate_with, Backend};
+
+        assert!(available_backends().any(|b| b == Backend::Libcore));
+        assert!(available_backends().any(|b| b == Backend::fastest()));
+        for &backend in Backend::ALL {
+            assert_eq!(Backend::from_name(backend.name()), Some(backend));
+            assert_eq!(backend.name().parse(), Ok(backend));
+        }
+        assert!("avx".parse::<Backend>().is_err());
+        for backend in available_backends() {
+            let is_utf8 = |bytes: &[u8]| is_utf8_with(backend, bytes);
+            create_tests!(is_utf8);
+            let validate = |bytes: &[u8]| validate_with(backend, bytes);
+            create_validate_tests!(validate);
+        }
+    }
+
+    #[test]
+    fn test_ascii_transitions() {
+        use super::{available_backends, validate_with};
+        use std::vec::Vec;
+
+        // Characters that start, end or straddle a block next to ASCII ones,
+        // complete and with their last byte missing.
+        for backend in available_backends() {
+            for &char in &["\u{E9}", "\u{20AC}", "\u{1F600}"] {
+                for offset in 0..160 {
+                    let mut input = Vec::new();
+                    input.resize(offset, b'a');
+                    input.extend_from_slice(char.as_bytes());
+                    input.resize(256, b'a');
+                    assert!(validate_with(backend, &input).is_ok());
+                    input.remove(offset + char.len() - 1);
+                    assert_eq!(
+                        validate_with(backend, &input).map_err(|e| e.valid_up_to()),
+                        Err(offset),
+                        "{}",
+                        backend.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_early_errors() {
+        use super::{available_backends, validate_with, ERROR_CHECK_INTERVAL};
+        use std::vec::Vec;
+
+        // Errors just before, at and just after the points where the error
+        // register is tested, and at the very end.
+        let mut valid = Vec::new();
+        while valid.len() < 3 * ERROR_CHECK_INTERVAL {
+            valid.extend_from_slice("a\u{E9}\u{20AC}\u{1F600}".as_bytes());
+        }
+        valid.truncate(3 * ERROR_CHECK_INTERVAL + 10);
+        for backend in available_backends() {
+            assert!(validate_with(backend, &valid).is_ok());
+            for &pos in &[
+                0,
+                ERROR_CHECK_INTERVAL - 1,
+                ERROR_CHECK_INTERVAL,
+                ERROR_CHECK_INTERVAL + 1,
+                2 * ERROR_CHECK_INTERVAL - 33,
+                valid.len() - 1,
+            ] {
+                let mut input = valid.clone();
+                input[pos] = 0xFF;
+                assert_eq!(
+                    validate_with(backend, &input).map_err(|e| e.valid_up_to()),
+                    core::str::from_utf8(&input)
+                        .map(|_| ())
+                        .map_err(|e| e.valid_up_to()),
+                    "{} {}",
+                    backend.name(),
+                    pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tails() {
+        use super::{available_backends, validate_with};
+        use std::vec::Vec;
+
+        // Inputs of every length up to a few blocks that end in a character,
+        // complete or cut short, so that it lands in tails of every length.
+        for backend in available_backends() {
+            for len in 0..100 {
+                for &char in &["\u{E9}", "\u{20AC}", "\u{1F600}"] {
+                    for end in 1..=char.len() {
+                        let mut input = Vec::new();
+                        input.resize(len, b'a');
+                        input.extend_from_slice(&char.as_bytes()[..end]);
+                        assert_eq!(
+                            validate_with(backend, &input).map_err(|e| e.valid_up_to()),
+                            core::str::from_utf8(&input)
+                                .map(|_| ())
+                                .map_err(|e| e.valid_up_to()),
+                            "{} {}",
+                            backend.name(),
+                            input.len()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(
+        not(feature = "scalar-only"),
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
+    fn test_cpu_detection() {
+        use super::cpu;
+
+        assert_eq!(
+            cpu::has_avx2(),
+            is_x86_feature_detected!("avx") && is_x86_feature_detected!("avx2")
+        );
+        assert_eq!(cpu::has_sse2(), is_x86_feature_detected!("sse2"));
+        assert_eq!(
+            cpu::has_sse41(),
+            is_x86_feature_detected!("sse2")
+                && is_x86_feature_detected!("ssse3")
+                && is_x86_feature_detected!("sse4.1")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "scalar-only")]
+    fn test_scalar_only() {
+        use super::{available_backends, Backend};
+
+        assert!(available_backends().eq(core::iter::once(Backend::Libcore)));
+    }
+
+    #[test]
+    fn test_validators() {
+        use super::{
+            Lemire, LemireAsciiPath, Libcore, Lookup, LookupAsciiPath, Range, Swar, Validator,
+        };
+
+        fn check<V: Validator>(validator: V) {
+            let is_utf8 = |bytes: &[u8]| validator.is_utf8(bytes);
+            create_tests!(is_utf8);
+            let validate = |bytes: &[u8]| validator.validate(bytes);
+            create_validate_tests!(validate);
+        }
+        assert_eq!(core::mem::size_of::<Lemire>(), 0);
+        assert_eq!(core::mem::size_of::<LemireAsciiPath>(), 0);
+        assert_eq!(core::mem::size_of::<Lookup>(), 0);
+        assert_eq!(core::mem::size_of::<Range>(), 0);
+        assert_eq!(core::mem::size_of::<LookupAsciiPath>(), 0);
+        check(Libcore);
+        check(Swar);
+        if let Some(validator) = Lemire::new() {
+            check(validator);
+        }
+        if let Some(validator) = LemireAsciiPath::new() {
+            check(validator);
+        }
+        if let Some(validator) = Lookup::new() {
+            check(validator);
+        }
+        if let Some(validator) = Range::new() {
+            check(validator);
+        }
+        if let Some(validator) = LookupAsciiPath::new() {
+            check(validator);
+        }
+    }
+
+    #[test]
+    fn test_lookup_ascii_path() {
+        use super::{LookupAsciiPath, Validator};
+        use std::vec::Vec;
+
+        let validator = match LookupAsciiPath::new() {
+            Some(validator) => validator,
+            None => return,
+        };
+        let check = |input: &[u8]| {
+            assert_eq!(
+                validator.is_utf8(input),
+                core::str::from_utf8(input).is_ok(),
+                "{}",
+                input.len()
+            );
+        };
+
+        // Characters, cut short or not, and runs of continuations wherever
+        // the ASCII skip can stop, and around the first two error checks,
+        // after ASCII or CJK.
+        let probes: &[&[u8]] = &[
+            "\u{E9}".as_bytes(),
+            "\u{20AC}".as_bytes(),
+            "\u{1F600}".as_bytes(),
+            b"\xF0\x9F\x98",
+            b"\xE2\x82",
+            b"\x80",
+            b"\x80\x80",
+            b"\x80\x80\x80",
+            b"\x80\x80\x80\x80",
+            b"\x80\x80\x80\x80\x80",
+            b"\xED\xA0\x80",
+        ];
+        for &filler in &["a", "\u{4E2D}"] {
+            for &(start, end) in &[(0, 400), (4088, 4100), (8184, 8196)] {
+                for len in start..end {
+                    for &probe in probes {
+                        let mut input = Vec::new();
+                        while input.len() < len {
+                            input.extend_from_slice(filler.as_bytes());
+                        }
+                        input.truncate(len);
+                        input.extend_from_slice(probe);
+                        input.resize(input.len() + 20, b'a');
+                        check(&input);
+                    }
+                }
+            }
+        }
+
+        // Markup around CJK text, so that the skip starts and stops, with an
+        // error in one place at a time.
+        let mut document = Vec::new();
+        for i in 0..40 {
+            let (tag, text, times) = if i % 5 < 2 {
+                ("<p class=\"text\">", "\u{4E2D}\u{6587}\u{6587}\u{672C}", 12)
+            } else {
+                ("<div id=\"markup\">", "</div>\n", 1)
+            };
+            for _ in 0..i * 7 % 50 + 1 {
+                document.extend_from_slice(tag.as_bytes());
+                for _ in 0..times {
+                    document.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        check(&document);
+        for pos in (0..document.len()).step_by(397) {
+            let mut input = document.clone();
+            input[pos] = 0xFF;
+            check(&input);
+        }
+    }
+
+    #[test]
+    fn test_libcore_validate() {
+        use super::libcore::validate;
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_simd_validate() {
+        if is_x86_feature_detected!("avx2") {
+            let validate = |bytes: &[u8]| unsafe { super::lemire::avx::validate(bytes) };
+            create_validate_tests!(validate);
+            let validate = |bytes: &[u8]| unsafe { super::range::avx::validate(bytes) };
+            create_validate_tests!(validate);
+            let validate = |bytes: &[u8]| unsafe { super::lookup::avx::validate(bytes) };
+            create_validate_tests!(validate);
+        }
+        if is_x86_feature_detected!("sse4.1") {
+            let validate = |bytes: &[u8]| unsafe { super::lemire::sse::validate(bytes) };
+            create_validate_tests!(validate);
+            let validate = |bytes: &[u8]| unsafe { super::range::sse::validate(bytes) };
+            create_validate_tests!(validate);
+            let validate = |bytes: &[u8]| unsafe { super::lookup::sse::validate(bytes) };
+            create_validate_tests!(validate);
+        }
+        if is_x86_feature_detected!("sse2") {
+            #[cfg(target_feature = "sse2")]
+            let validate = super::range::sse2::validate;
+            #[cfg(not(target_feature = "sse2"))]
+            let validate = |bytes: &[u8]| unsafe { super::range::sse2::validate(bytes) };
+            create_validate_tests!(validate);
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_neon_validate() {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        let validate = |bytes: &[u8]| unsafe { super::lemire::neon::validate(bytes) };
+        create_validate_tests!(validate);
+        let validate = |bytes: &[u8]| unsafe { super::range::neon::validate(bytes) };
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_lemire_avx() {
-        use super::lemire::avx::is_utf8;
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::lemire::avx::is_utf8(bytes) };
         create_tests!(is_utf8);
     }
 
     #[test]
-    #[cfg(all(target_feature = "avx", target_feature = "avx2"))]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_lemire_avx_ascii() {
-        use super::lemire::avx::is_utf8_ascii_path;
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let is_utf8_ascii_path =
+            |bytes: &[u8]| unsafe { super::lemire::avx::is_utf8_ascii_path(bytes) };
+        create_tests!(is_utf8_ascii_path);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_lemire_sse_ascii() {
+        if !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let is_utf8_ascii_path =
+            |bytes: &[u8]| unsafe { super::lemire::sse::is_utf8_ascii_path(bytes) };
         create_tests!(is_utf8_ascii_path);
     }
 
     #[test]
-    #[cfg(target_feature = "sse4.1")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_lemire_sse() {
-        use super::lemire::sse::is_utf8;
+        if !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::lemire::sse::is_utf8(bytes) };
         create_tests!(is_utf8);
     }
 
@@ -254,16 +1019,279 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "sse4.1")]
+    fn test_small() {
+        use super::small::is_utf8;
+        use std::vec::Vec;
+        create_tests!(is_utf8);
+
+        // Every pair of bytes, followed by bytes that are continuations or
+        // not. Then some of them after ASCII that ends in each position of a
+        // word.
+        let mut input = Vec::new();
+        for ascii in 0..20 {
+            let firsts: Vec<u8> = if ascii == 0 {
+                (0..=255).collect()
+            } else {
+                vec![0x41, 0x80, 0xC3, 0xE0, 0xED, 0xF0, 0xF4, 0xFF]
+            };
+            for &a in &firsts {
+                for b in 0..=255u8 {
+                    for &c in &[0x41, 0x80, 0xBF] {
+                        for &d in &[0x41, 0x8F, 0x90] {
+                            input.clear();
+                            input.resize(ascii, b'a');
+                            input.extend_from_slice(&[a, b, c, d]);
+                            for len in ascii + 1..=input.len() {
+                                assert_eq!(
+                                    is_utf8(&input[..len]),
+                                    core::str::from_utf8(&input[..len]).is_ok(),
+                                    "{:x?}",
+                                    &input[..len]
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_swar() {
+        use super::swar::{is_utf8, validate};
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    fn test_swar_ascii_runs() {
+        use super::swar::is_utf8;
+        use std::vec::Vec;
+
+        // Runs of ASCII are skipped with aligned loads, so the input starts at
+        // every offset from an aligned address, with a character in every
+        // position of the run, complete and with its last byte missing.
+        for &char in &["\u{E9}", "\u{20AC}", "\u{1F600}"] {
+            for pos in 0..100 {
+                for end in [char.len() - 1, char.len()] {
+                    let mut buffer = Vec::new();
+                    buffer.resize(8 + pos, b'a');
+                    buffer.extend_from_slice(&char.as_bytes()[..end]);
+                    buffer.resize(8 + 200, b'a');
+                    for shift in 0..8 {
+                        let input = &buffer[shift..];
+                        assert_eq!(
+                            is_utf8(input),
+                            core::str::from_utf8(input).is_ok(),
+                            "{} {} {}",
+                            pos,
+                            end,
+                            shift
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "portable-simd")]
+    fn test_lookup_portable() {
+        use super::lookup::portable::{is_utf8_lanes, validate};
+        let is_utf8 = is_utf8_lanes::<16>;
+        create_tests!(is_utf8);
+        let is_utf8 = is_utf8_lanes::<32>;
+        create_tests!(is_utf8);
+        let is_utf8 = is_utf8_lanes::<64>;
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_range_sse() {
-        use super::range::sse::is_utf8;
+        if !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::range::sse::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_range_sse_ascii() {
+        if !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let is_utf8_ascii_path =
+            |bytes: &[u8]| unsafe { super::range::sse::is_utf8_ascii_path(bytes) };
+        create_tests!(is_utf8_ascii_path);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_range_sse2() {
+        if !is_x86_feature_detected!("sse2") {
+            return;
+        }
+        #[cfg(target_feature = "sse2")]
+        let is_utf8 = super::range::sse2::is_utf8;
+        #[cfg(not(target_feature = "sse2"))]
+        let is_utf8 = |bytes: &[u8]| unsafe { super::range::sse2::is_utf8(bytes) };
         create_tests!(is_utf8);
     }
 
     #[test]
-    #[cfg(all(target_feature = "avx", target_feature = "avx2"))]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_range_avx() {
-        use super::range::avx::is_utf8;
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::range::avx::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_range_avx_ascii() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let is_utf8_ascii_path =
+            |bytes: &[u8]| unsafe { super::range::avx::is_utf8_ascii_path(bytes) };
+        create_tests!(is_utf8_ascii_path);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_lookup_sse() {
+        if !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::lookup::sse::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_lookup_avx() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::lookup::avx::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_lemire_neon() {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::lemire::neon::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_range_neon() {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        let is_utf8 = |bytes: &[u8]| unsafe { super::range::neon::is_utf8(bytes) };
+        create_tests!(is_utf8);
+    }
+
+    #[test]
+    #[cfg(all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"))]
+    fn test_range_armv7() {
+        use super::range::armv7::{is_utf8, validate};
+
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "powerpc-vsx",
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_feature = "vsx"
+    ))]
+    fn test_range_vsx() {
+        use super::range::vsx::{is_utf8, validate};
+        use std::vec::Vec;
+
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+
+        // Tails of every length, which are padded with zeros by `vec_perm`,
+        // ending in a character that is cut short or complete, after
+        // characters that straddle the blocks before them.
+        let mut text = Vec::new();
+        while text.len() < 48 {
+            text.extend_from_slice("a\u{E9}\u{20AC}\u{1F600}".as_bytes());
+        }
+        for len in 16..48 {
+            for &char in &["\u{E9}", "\u{20AC}", "\u{1F600}"] {
+                for end in 1..=char.len() {
+                    let mut input = text[..len].to_vec();
+                    input.extend_from_slice(&char.as_bytes()[..end]);
+                    assert_eq!(
+                        is_utf8(&input),
+                        core::str::from_utf8(&input).is_ok(),
+                        "{} {}",
+                        len,
+                        end
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "riscv64", target_feature = "v"))]
+    fn test_lookup_rvv() {
+        use super::lookup::rvv::{is_utf8, validate};
+        use std::vec::Vec;
+
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+
+        // Characters at every position of the first strips, which are 128
+        // bytes long at most (VLEN 512), so that some straddle two strips,
+        // complete and with their last byte missing. The last strips can be
+        // shorter, so the inputs are of a few lengths.
+        for &len in &[300, 301, 302, 303] {
+            for &char in &["\u{E9}", "\u{20AC}", "\u{1F600}"] {
+                for pos in 0..len - char.len() {
+                    let mut input = Vec::new();
+                    input.resize(pos, b'a');
+                    input.extend_from_slice(char.as_bytes());
+                    input.resize(len, b'a');
+                    assert!(is_utf8(&input), "{} {}", len, pos);
+                    input.remove(pos + char.len() - 1);
+                    assert!(!is_utf8(&input), "{} {}", len, pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    fn test_lemire_simd128() {
+        use super::lemire::simd128::{is_utf8, validate};
+
+        create_tests!(is_utf8);
+        create_validate_tests!(validate);
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    fn test_range_simd128() {
+        use super::range::simd128::{is_utf8, validate};
+
         create_tests!(is_utf8);
+        create_validate_tests!(validate);
     }
 }
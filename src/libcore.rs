@@ -1,14 +1,9 @@
 //! The UTF-8 validation algorithm used in Rust's libcore.
 //!
 //! It contains a fast-path for ASCII text.
+use crate::Utf8Error;
 use core::mem;
 
-#[derive(Copy, Eq, PartialEq, Clone, Debug)]
-struct Utf8Error {
-    valid_up_to: usize,
-    error_len: Option<u8>,
-}
-
 // Use truncation to fit u64 into usize
 const NONASCII_MASK: usize = 0x80808080_80808080u64 as usize;
 
@@ -40,7 +35,7 @@ fn run_utf8_validation(v: &[u8]) -> Result<(), Utf8Error> {
                 return Err(Utf8Error {
                     valid_up_to: old_offset,
                     error_len: $error_len,
-                });
+                })
             };
         }
 
@@ -165,6 +160,12 @@ static UTF8_CHAR_WIDTH: [u8; 256] = [
     4,4,4,4,4,0,0,0,0,0,0,0,0,0,0,0, // 0xFF
 ];
 
+/// Given a first byte, determines how many bytes are in this UTF-8 character.
+#[inline]
+pub(crate) fn utf8_char_width(b: u8) -> usize {
+    UTF8_CHAR_WIDTH[b as usize] as usize
+}
+
 /// Mask of the value bits of a continuation byte.
 const CONT_MASK: u8 = 0b0011_1111;
 /// Value of the tag bits (tag mask is !CONT_MASK) of a continuation byte.
@@ -173,3 +174,11 @@ const TAG_CONT_U8: u8 = 0b1000_0000;
 pub fn is_utf8(bytes: &[u8]) -> bool {
     run_utf8_validation(bytes).is_ok()
 }
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The error is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    run_utf8_validation(bytes)
+}
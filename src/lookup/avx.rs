@@ -0,0 +1,342 @@
+//! # AVX implementation of the lookup algorithm
+//!
+//! ## Target specific intrinsics used:
+//! ### SSE2
+//! * _mm_loadu_si128
+//!
+//! ### AVX
+//! * _mm256_loadu_si256
+//! * _mm256_set1_epi8
+//! * _mm256_setzero_si256
+//! * _mm256_testz_si256
+//!
+//! ### AVX2
+//! * _mm256_alignr_epi8
+//! * _mm256_and_si256
+//! * _mm256_broadcastsi128_si256
+//! * _mm256_movemask_epi8
+//! * _mm256_or_si256
+//! * _mm256_permute2x128_si256
+//! * _mm256_shuffle_epi8
+//! * _mm256_srli_epi16
+//! * _mm256_subs_epu8
+//! * _mm256_xor_si256
+use super::{BYTE_1_HIGH, BYTE_1_LOW, BYTE_2_HIGH};
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+#[inline(always)]
+fn load(bytes: &[u8]) -> __m256i {
+    debug_assert!(bytes.len() >= 32);
+    unsafe { _mm256_loadu_si256(bytes.as_ptr() as *const __m256i) }
+}
+
+// The table is repeated in both lanes, as `_mm256_shuffle_epi8` looks up
+// each lane separately.
+#[inline(always)]
+fn lookup_16(table: &[u8; 16], indices: __m256i) -> __m256i {
+    unsafe {
+        let table = _mm256_broadcastsi128_si256(_mm_loadu_si128(table.as_ptr() as *const __m128i));
+        _mm256_shuffle_epi8(table, indices)
+    }
+}
+
+#[inline(always)]
+fn high_nibbles(bytes: __m256i) -> __m256i {
+    unsafe { _mm256_and_si256(_mm256_srli_epi16(bytes, 4), _mm256_set1_epi8(0x0F)) }
+}
+
+#[inline(always)]
+fn low_nibbles(bytes: __m256i) -> __m256i {
+    unsafe { _mm256_and_si256(bytes, _mm256_set1_epi8(0x0F)) }
+}
+
+// (prev_input, input) << N bytes, across the two lanes
+#[inline(always)]
+fn prev1(input: __m256i, prev_input: __m256i) -> __m256i {
+    unsafe {
+        _mm256_alignr_epi8(
+            input,
+            _mm256_permute2x128_si256(prev_input, input, 0x21),
+            16 - 1,
+        )
+    }
+}
+#[inline(always)]
+fn prev2(input: __m256i, prev_input: __m256i) -> __m256i {
+    unsafe {
+        _mm256_alignr_epi8(
+            input,
+            _mm256_permute2x128_si256(prev_input, input, 0x21),
+            16 - 2,
+        )
+    }
+}
+#[inline(always)]
+fn prev3(input: __m256i, prev_input: __m256i) -> __m256i {
+    unsafe {
+        _mm256_alignr_epi8(
+            input,
+            _mm256_permute2x128_si256(prev_input, input, 0x21),
+            16 - 3,
+        )
+    }
+}
+
+// The errors of each byte and the one before it.
+#[inline(always)]
+fn check_special_cases(input: __m256i, prev1: __m256i) -> __m256i {
+    unsafe {
+        let byte_1_high = lookup_16(&BYTE_1_HIGH, high_nibbles(prev1));
+        let byte_1_low = lookup_16(&BYTE_1_LOW, low_nibbles(prev1));
+        let byte_2_high = lookup_16(&BYTE_2_HIGH, high_nibbles(input));
+        _mm256_and_si256(_mm256_and_si256(byte_1_high, byte_1_low), byte_2_high)
+    }
+}
+
+// Two continuations in a row are only allowed as the third or fourth byte of
+// a character, which the special cases cannot tell. Flips their `TWO_CONTS`
+// bit, so that it is set exactly where a continuation is missing or
+// superfluous.
+#[inline(always)]
+fn check_multibyte_lengths(input: __m256i, prev_input: __m256i, special_cases: __m256i) -> __m256i {
+    unsafe {
+        // Only 111_____ will be >= 0x80
+        let is_third_byte = _mm256_subs_epu8(
+            prev2(input, prev_input),
+            _mm256_set1_epi8((0xE0 - 0x80) as i8),
+        );
+        // Only 1111____ will be >= 0x80
+        let is_fourth_byte = _mm256_subs_epu8(
+            prev3(input, prev_input),
+            _mm256_set1_epi8((0xF0 - 0x80) as i8),
+        );
+        let must_be_2_3_continuation = _mm256_and_si256(
+            _mm256_or_si256(is_third_byte, is_fourth_byte),
+            _mm256_set1_epi8(0x80u8 as i8),
+        );
+        _mm256_xor_si256(must_be_2_3_continuation, special_cases)
+    }
+}
+
+// Non-zero if the block ends in the middle of a character.
+#[inline(always)]
+fn is_incomplete(input: __m256i) -> __m256i {
+    unsafe { _mm256_subs_epu8(input, load(&tail::INCOMPLETE_MAX)) }
+}
+
+#[inline(always)]
+fn is_ascii(input: __m256i) -> bool {
+    unsafe { _mm256_movemask_epi8(input) == 0 }
+}
+
+struct State {
+    prev_input: __m256i,
+    prev_incomplete: __m256i,
+    error: __m256i,
+}
+
+impl Default for State {
+    #[inline(always)]
+    fn default() -> Self {
+        unsafe {
+            State {
+                prev_input: _mm256_setzero_si256(),
+                prev_incomplete: _mm256_setzero_si256(),
+                error: _mm256_setzero_si256(),
+            }
+        }
+    }
+}
+
+impl State {
+    #[inline(always)]
+    fn check_bytes(&mut self, input: __m256i) {
+        unsafe {
+            let special_cases = check_special_cases(input, prev1(input, self.prev_input));
+            self.error = _mm256_or_si256(
+                self.error,
+                check_multibyte_lengths(input, self.prev_input, special_cases),
+            );
+            self.prev_incomplete = is_incomplete(input);
+            self.prev_input = input;
+        }
+    }
+
+    // An ASCII block is only an error if the one before it was incomplete.
+    #[inline(always)]
+    fn check_ascii(&mut self, last_input: __m256i) {
+        unsafe {
+            self.error = _mm256_or_si256(self.error, self.prev_incomplete);
+            self.prev_incomplete = _mm256_setzero_si256();
+            self.prev_input = last_input;
+        }
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { _mm256_testz_si256(self.error, self.error) == 0 }
+    }
+
+    #[inline(always)]
+    fn is_valid(&self) -> bool {
+        unsafe {
+            let error = _mm256_or_si256(self.error, self.prev_incomplete);
+            _mm256_testz_si256(error, error) != 0
+        }
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut state = State::default();
+
+    // 64 bytes at a time, skipping the checks if they are all ASCII
+    let mut blocks = bytes.chunks_exact(64);
+    while blocks.len() != 0 {
+        for block in (&mut blocks).take(ERROR_CHECK_INTERVAL / 64) {
+            let input_0 = load(&block[..32]);
+            let input_1 = load(&block[32..]);
+            if is_ascii(_mm256_or_si256(input_0, input_1)) {
+                state.check_ascii(input_1);
+            } else {
+                state.check_bytes(input_0);
+                state.check_bytes(input_1);
+            }
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    let rest = blocks.remainder();
+    if rest.len() >= 32 {
+        state.check_bytes(load(rest));
+    }
+    // last part
+    let rest = &rest[rest.len() / 32 * 32..];
+    if !rest.is_empty() {
+        state.check_bytes(tail::avx(bytes, bytes.len() - rest.len()));
+    }
+
+    state.is_valid()
+}
+
+// How many blocks in a row must be ASCII before `is_utf8_ascii_path` starts to
+// skip ASCII. One is too few on markup with short words of other scripts in
+// it, where the skip keeps stopping at once, and eight give up some of the
+// gain on ASCII text.
+const SKIP_AFTER_BLOCKS: u32 = 4;
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// Works like [`is_utf8`], but keeps track of which of the recent 64-byte
+/// blocks were ASCII. Once the last four were, it skips ahead 128 bytes at a
+/// time for as long as the input stays ASCII, and goes back to checking one
+/// block at a time where it does not. This is up to twice as fast on input
+/// with long runs of ASCII, and a few percent slower on other input.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut state = State::default();
+    let skip_mask = (1 << SKIP_AFTER_BLOCKS) - 1;
+    // One bit for each of the recent blocks, set if it was ASCII
+    let mut recent: u32 = 0;
+
+    let mut rest = bytes;
+    while rest.len() >= 64 {
+        let mut blocks = rest.len().min(ERROR_CHECK_INTERVAL) / 64;
+        while blocks != 0 {
+            if recent & skip_mask == skip_mask {
+                // 128 bytes at a time, as long as they are all ASCII. The
+                // block before was ASCII, so no character is cut off here.
+                let mut skipped = 0;
+                while rest.len() - skipped >= 128 {
+                    let ptr = rest.as_ptr().add(skipped);
+                    let input_0 = _mm256_loadu_si256(ptr as *const __m256i);
+                    let input_1 = _mm256_loadu_si256(ptr.add(32) as *const __m256i);
+                    let input_2 = _mm256_loadu_si256(ptr.add(64) as *const __m256i);
+                    let input_3 = _mm256_loadu_si256(ptr.add(96) as *const __m256i);
+                    if !is_ascii(_mm256_or_si256(
+                        _mm256_or_si256(input_0, input_1),
+                        _mm256_or_si256(input_2, input_3),
+                    )) {
+                        break;
+                    }
+                    skipped += 128;
+                }
+                // At least one block was checked before, so this stays
+                // within `bytes`.
+                state.prev_input =
+                    _mm256_loadu_si256(rest.as_ptr().add(skipped).sub(32) as *const __m256i);
+                rest = rest.get_unchecked(skipped..);
+                recent = 0;
+                blocks = rest.len().min(ERROR_CHECK_INTERVAL) / 64;
+                if blocks == 0 {
+                    break;
+                }
+            }
+            let ptr = rest.as_ptr();
+            let input_0 = _mm256_loadu_si256(ptr as *const __m256i);
+            let input_1 = _mm256_loadu_si256(ptr.add(32) as *const __m256i);
+            if is_ascii(_mm256_or_si256(input_0, input_1)) {
+                state.check_ascii(input_1);
+                recent = (recent << 1) | 1;
+            } else {
+                state.check_bytes(input_0);
+                state.check_bytes(input_1);
+                recent <<= 1;
+            }
+            rest = rest.get_unchecked(64..);
+            blocks -= 1;
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    if rest.len() >= 32 {
+        state.check_bytes(load(rest));
+    }
+    // last part
+    let rest = &rest[rest.len() / 32 * 32..];
+    if !rest.is_empty() {
+        state.check_bytes(tail::avx(bytes, bytes.len() - rest.len()));
+    }
+
+    state.is_valid()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -0,0 +1,125 @@
+//! John Keiser and Daniel Lemire's "lookup" algorithm
+//!
+//! It is the algorithm of [simdjson] and [simdutf], and is described in the
+//! paper [Validating UTF-8 In Less Than One Instruction Per Byte][1]. Each
+//! pair of adjacent bytes is classified with three 16-entry nibble tables,
+//! whose results are ANDed into a bit set of the errors the pair could be
+//! part of. What cannot be seen in a pair of bytes, a third or fourth byte
+//! that is missing or superfluous, is caught by checking which bytes must be
+//! continuations of a lead byte two or three positions back.
+//!
+//! [simdjson]: https://github.com/simdjson/simdjson
+//! [simdutf]: https://github.com/simdutf/simdutf
+//! [1]: https://arxiv.org/abs/2010.03090
+// The tables below are only used by the backends compiled for the target.
+#![cfg_attr(
+    not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "riscv64", target_feature = "v"),
+        feature = "portable-simd"
+    )),
+    allow(dead_code)
+)]
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
+pub mod avx;
+#[cfg(feature = "portable-simd")]
+#[cfg_attr(dox, doc(cfg(feature = "portable-simd")))]
+pub mod portable;
+#[cfg(all(target_arch = "riscv64", target_feature = "v"))]
+#[cfg_attr(dox, doc(cfg(all(target_arch = "riscv64", target_feature = "v"))))]
+pub mod rvv;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
+pub mod sse;
+
+// The errors a pair of bytes can be part of, one bit each. The first column
+// is the byte before (byte 1), the second the byte itself (byte 2).
+const TOO_SHORT: u8 = 1 << 0; // 11______ 0_______
+                              // 11______ 11______
+const TOO_LONG: u8 = 1 << 1; // 0_______ 10______
+const OVERLONG_3: u8 = 1 << 2; // 11100000 100_____
+const TOO_LARGE: u8 = 1 << 3; // 11110100 1001____
+                              // 11110100 101_____
+                              // 11110101 1001____
+                              // 11110101 101_____
+                              // 1111011_ 1001____
+                              // 1111011_ 101_____
+                              // 11111___ 1001____
+                              // 11111___ 101_____
+const SURROGATE: u8 = 1 << 4; // 11101101 101_____
+const OVERLONG_2: u8 = 1 << 5; // 1100000_ 10______
+const TOO_LARGE_1000: u8 = 1 << 6; // 11110101 1000____
+                                   // 1111011_ 1000____
+                                   // 11111___ 1000____
+const OVERLONG_4: u8 = 1 << 6; // 11110000 1000____
+const TWO_CONTS: u8 = 1 << 7; // 10______ 10______
+
+// These errors do not depend on the low nibble of byte 1.
+const CARRY: u8 = TOO_SHORT | TOO_LONG | TWO_CONTS;
+
+// Indexed by the high nibble of byte 1.
+#[rustfmt::skip]
+static BYTE_1_HIGH: [u8; 16] = [
+    // 0_______ ________ <ASCII in byte 1>
+    TOO_LONG, TOO_LONG, TOO_LONG, TOO_LONG,
+    TOO_LONG, TOO_LONG, TOO_LONG, TOO_LONG,
+    // 10______ ________ <continuation in byte 1>
+    TWO_CONTS, TWO_CONTS, TWO_CONTS, TWO_CONTS,
+    // 1100____ ________ <two byte lead in byte 1>
+    TOO_SHORT | OVERLONG_2,
+    // 1101____ ________ <two byte lead in byte 1>
+    TOO_SHORT,
+    // 1110____ ________ <three byte lead in byte 1>
+    TOO_SHORT | OVERLONG_3 | SURROGATE,
+    // 1111____ ________ <four+ byte lead in byte 1>
+    TOO_SHORT | TOO_LARGE | TOO_LARGE_1000 | OVERLONG_4,
+];
+
+// Indexed by the low nibble of byte 1.
+#[rustfmt::skip]
+static BYTE_1_LOW: [u8; 16] = [
+    // ____0000 ________
+    CARRY | OVERLONG_3 | OVERLONG_2 | OVERLONG_4,
+    // ____0001 ________
+    CARRY | OVERLONG_2,
+    // ____001_ ________
+    CARRY,
+    CARRY,
+    // ____0100 ________
+    CARRY | TOO_LARGE,
+    // ____0101 ________
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    // ____011_ ________
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    // ____1___ ________
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    // ____1101 ________
+    CARRY | TOO_LARGE | TOO_LARGE_1000 | SURROGATE,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+];
+
+// Indexed by the high nibble of byte 2.
+#[rustfmt::skip]
+static BYTE_2_HIGH: [u8; 16] = [
+    // ________ 0_______ <ASCII in byte 2>
+    TOO_SHORT, TOO_SHORT, TOO_SHORT, TOO_SHORT,
+    TOO_SHORT, TOO_SHORT, TOO_SHORT, TOO_SHORT,
+    // ________ 1000____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE_1000 | OVERLONG_4,
+    // ________ 1001____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE,
+    // ________ 101_____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+    // ________ 11______
+    TOO_SHORT, TOO_SHORT, TOO_SHORT, TOO_SHORT,
+];
@@ -0,0 +1,201 @@
+//! # Portable implementation of the lookup algorithm
+//!
+//! Written once against `core::simd`, for vectors of 16, 32 or 64 bytes.
+//! LLVM lowers it to the vector instructions of the target, so it covers
+//! targets that have no hand-written backend. `swizzle_dyn` takes the place
+//! of `pshufb` and `vqtbl1q_u8`. It looks up across the whole vector rather
+//! than within 16-byte lanes, so the 16-entry tables are repeated to fill
+//! it.
+//!
+//! Targets without vector instructions get a scalar emulation, which is
+//! slower than the [SWAR implementation](crate::swar).
+//!
+//! `core::simd` is unstable, so this module needs a nightly compiler and the
+//! `portable-simd` feature.
+use super::{BYTE_1_HIGH, BYTE_1_LOW, BYTE_2_HIGH};
+use crate::{libcore, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::array;
+use core::simd::prelude::*;
+
+/// The vector size [`is_utf8`] uses: 64 bytes with AVX-512, 32 with AVX2 and
+/// 16 everywhere else.
+pub const LANES: usize = if cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "avx512bw"
+)) {
+    64
+} else if cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "avx2"
+)) {
+    32
+} else {
+    16
+};
+
+#[inline(always)]
+fn lookup_16<const N: usize>(table: &[u8; 16], indices: Simd<u8, N>) -> Simd<u8, N> {
+    Simd::from_array(array::from_fn(|i| table[i % 16])).swizzle_dyn(indices)
+}
+
+// (prev_input, input) << K bytes
+#[inline(always)]
+fn prev<const K: usize, const N: usize>(
+    prev_input: Simd<u8, N>,
+    input: Simd<u8, N>,
+) -> Simd<u8, N> {
+    let index = Simd::from_array(array::from_fn(|i| i as u8));
+    index.simd_lt(Simd::splat(K as u8)).select(
+        prev_input.rotate_elements_right::<K>(),
+        input.rotate_elements_right::<K>(),
+    )
+}
+
+// The errors of each byte and the one before it.
+#[inline(always)]
+fn check_special_cases<const N: usize>(input: Simd<u8, N>, prev1: Simd<u8, N>) -> Simd<u8, N> {
+    let byte_1_high = lookup_16(&BYTE_1_HIGH, prev1 >> 4);
+    let byte_1_low = lookup_16(&BYTE_1_LOW, prev1 & Simd::splat(0x0F));
+    let byte_2_high = lookup_16(&BYTE_2_HIGH, input >> 4);
+    byte_1_high & byte_1_low & byte_2_high
+}
+
+// Two continuations in a row are only allowed as the third or fourth byte of
+// a character, which the special cases cannot tell. Flips their `TWO_CONTS`
+// bit, so that it is set exactly where a continuation is missing or
+// superfluous.
+#[inline(always)]
+fn check_multibyte_lengths<const N: usize>(
+    input: Simd<u8, N>,
+    prev_input: Simd<u8, N>,
+    special_cases: Simd<u8, N>,
+) -> Simd<u8, N> {
+    // Only 111_____ will be >= 0x80
+    let is_third_byte = prev::<2, N>(prev_input, input).saturating_sub(Simd::splat(0xE0 - 0x80));
+    // Only 1111____ will be >= 0x80
+    let is_fourth_byte = prev::<3, N>(prev_input, input).saturating_sub(Simd::splat(0xF0 - 0x80));
+    let must_be_2_3_continuation = (is_third_byte | is_fourth_byte) & Simd::splat(0x80);
+    must_be_2_3_continuation ^ special_cases
+}
+
+// Non-zero if the block ends in the middle of a character.
+#[inline(always)]
+fn is_incomplete<const N: usize>(input: Simd<u8, N>) -> Simd<u8, N> {
+    let max = Simd::from_array(array::from_fn(|i| match N - i {
+        3 => 0xEF,
+        2 => 0xDF,
+        1 => 0xBF,
+        _ => 0xFF,
+    }));
+    input.saturating_sub(max)
+}
+
+struct State<const N: usize> {
+    prev_input: Simd<u8, N>,
+    prev_incomplete: Simd<u8, N>,
+    error: Simd<u8, N>,
+}
+
+impl<const N: usize> Default for State<N> {
+    #[inline(always)]
+    fn default() -> Self {
+        State {
+            prev_input: Simd::splat(0),
+            prev_incomplete: Simd::splat(0),
+            error: Simd::splat(0),
+        }
+    }
+}
+
+impl<const N: usize> State<N> {
+    #[inline(always)]
+    fn check_bytes(&mut self, input: Simd<u8, N>) {
+        let special_cases = check_special_cases(input, prev::<1, N>(self.prev_input, input));
+        self.error |= check_multibyte_lengths(input, self.prev_input, special_cases);
+        self.prev_incomplete = is_incomplete(input);
+        self.prev_input = input;
+    }
+
+    // An ASCII block is only an error if the one before it was incomplete.
+    #[inline(always)]
+    fn check_ascii(&mut self, last_input: Simd<u8, N>) {
+        self.error |= self.prev_incomplete;
+        self.prev_incomplete = Simd::splat(0);
+        self.prev_input = last_input;
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        self.error != Simd::splat(0)
+    }
+
+    #[inline(always)]
+    fn is_valid(&self) -> bool {
+        self.error | self.prev_incomplete == Simd::splat(0)
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8, checking `N` bytes at a time.
+///
+/// # Panics
+///
+/// Panics if `N` is not 16, 32 or 64.
+#[inline]
+pub fn is_utf8_lanes<const N: usize>(bytes: &[u8]) -> bool {
+    assert!(N == 16 || N == 32 || N == 64, "{} lanes", N);
+    let mut state = State::<N>::default();
+
+    // 64 bytes at a time, skipping the checks if they are all ASCII
+    let mut blocks = bytes.chunks_exact(64);
+    while blocks.len() != 0 {
+        for block in (&mut blocks).take(ERROR_CHECK_INTERVAL / 64) {
+            let any = block
+                .chunks_exact(N)
+                .fold(Simd::<u8, N>::splat(0), |any, chunk| {
+                    any | Simd::from_slice(chunk)
+                });
+            if any & Simd::splat(0x80) == Simd::splat(0) {
+                state.check_ascii(Simd::from_slice(&block[64 - N..]));
+            } else {
+                for chunk in block.chunks_exact(N) {
+                    state.check_bytes(Simd::from_slice(chunk));
+                }
+            }
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    let mut chunks = blocks.remainder().chunks_exact(N);
+    for chunk in &mut chunks {
+        state.check_bytes(Simd::from_slice(chunk));
+    }
+    // last part
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        state.check_bytes(Simd::load_or_default(rest));
+    }
+
+    state.is_valid()
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// It checks [`LANES`] bytes at a time.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    is_utf8_lanes::<LANES>(bytes)
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
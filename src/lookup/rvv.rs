@@ -0,0 +1,132 @@
+//! # RISC-V vector implementation of the lookup algorithm
+//!
+//! The nibble tables are looked up with `vrgather`. The vector length is
+//! only known at runtime, so the input is processed in strips of whatever
+//! length `vsetvli` grants. The three bytes before each strip are read back
+//! from memory and slid in front of it with `vslide1up`, which gives the
+//! bytes one, two and three positions back without any vector state carried
+//! between strips except the errors.
+//!
+//! There are no intrinsics for the vector extension, so the loop is written
+//! in inline assembly. This module is only compiled if the `v` target feature
+//! is enabled, so its functions are safe to call, and then always used.
+//!
+//! ## Instructions used:
+//! * vand.vv, vand.vi, vand.vx
+//! * vle8.v
+//! * vmv.s.x, vmv.v.i, vmv.x.s
+//! * vor.vv
+//! * vredor.vs
+//! * vrgather.vv
+//! * vsetivli, vsetvli
+//! * vslide1up.vx
+//! * vsrl.vi
+//! * vssubu.vx
+//! * vxor.vv
+use super::{BYTE_1_HIGH, BYTE_1_LOW, BYTE_2_HIGH};
+use crate::{libcore, small, Utf8Error};
+use core::arch::asm;
+
+// Whether `bytes` ends in the middle of a character.
+#[inline(always)]
+fn is_incomplete(bytes: &[u8]) -> bool {
+    match *bytes {
+        [.., a, b, c] => a >= 0xF0 || b >= 0xE0 || c >= 0xC0,
+        _ => unreachable!(),
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let error: usize;
+    // The loop only reads `bytes` and the tables. Every strip but the first
+    // is at least 8 bytes after the start, so the three bytes before it are
+    // in `bytes` too.
+    unsafe {
+        asm!(
+            "vsetvli {vl}, zero, e8, m2, ta, ma",
+            "vmv.v.i v8, 0",
+            "vsetivli zero, 16, e8, m2, ta, ma",
+            "vle8.v v2, ({byte_1_high})",
+            "vle8.v v4, ({byte_1_low})",
+            "vle8.v v6, ({byte_2_high})",
+            "li {p1}, 0",
+            "li {p2}, 0",
+            "li {p3}, 0",
+            "2:",
+            // The errors of a shorter last strip must not clear those of the
+            // strips before it, so the tail is undisturbed.
+            "vsetvli {vl}, {len}, e8, m2, tu, ma",
+            "vle8.v v10, ({ptr})",
+            // The bytes one, two and three positions back
+            "vslide1up.vx v12, v10, {p1}",
+            "vslide1up.vx v14, v12, {p2}",
+            "vslide1up.vx v16, v14, {p3}",
+            // The errors of each byte and the one before it
+            "vsrl.vi v18, v12, 4",
+            "vrgather.vv v22, v2, v18",
+            "vand.vi v18, v12, 15",
+            "vrgather.vv v20, v4, v18",
+            "vand.vv v22, v22, v20",
+            "vsrl.vi v18, v10, 4",
+            "vrgather.vv v20, v6, v18",
+            "vand.vv v22, v22, v20",
+            // Only 111_____ will be >= 0x80 after the first subtraction, and
+            // only 1111____ after the second.
+            "vssubu.vx v18, v14, {third}",
+            "vssubu.vx v20, v16, {fourth}",
+            "vor.vv v18, v18, v20",
+            "vand.vx v18, v18, {high}",
+            "vxor.vv v18, v18, v22",
+            "vor.vv v8, v8, v18",
+            "add {ptr}, {ptr}, {vl}",
+            "sub {len}, {len}, {vl}",
+            "lbu {p1}, -1({ptr})",
+            "lbu {p2}, -2({ptr})",
+            "lbu {p3}, -3({ptr})",
+            "bnez {len}, 2b",
+            "vsetvli {vl}, zero, e8, m2, ta, ma",
+            "vmv.s.x v24, zero",
+            "vredor.vs v24, v8, v24",
+            "vmv.x.s {error}, v24",
+            ptr = inout(reg) bytes.as_ptr() => _,
+            len = inout(reg) bytes.len() => _,
+            byte_1_high = in(reg) BYTE_1_HIGH.as_ptr(),
+            byte_1_low = in(reg) BYTE_1_LOW.as_ptr(),
+            byte_2_high = in(reg) BYTE_2_HIGH.as_ptr(),
+            third = in(reg) 0xE0u8 - 0x80,
+            fourth = in(reg) 0xF0u8 - 0x80,
+            high = in(reg) 0x80u8,
+            vl = out(reg) _,
+            p1 = out(reg) _,
+            p2 = out(reg) _,
+            p3 = out(reg) _,
+            error = lateout(reg) error,
+            out("v2") _, out("v3") _, out("v4") _, out("v5") _,
+            out("v6") _, out("v7") _, out("v8") _, out("v9") _,
+            out("v10") _, out("v11") _, out("v12") _, out("v13") _,
+            out("v14") _, out("v15") _, out("v16") _, out("v17") _,
+            out("v18") _, out("v19") _, out("v20") _, out("v21") _,
+            out("v22") _, out("v23") _, out("v24") _,
+            options(readonly, nostack),
+        );
+    }
+    error as u8 == 0 && !is_incomplete(bytes)
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -0,0 +1,217 @@
+//! # SSE implementation of the lookup algorithm
+//!
+//! ## Target specific intrinsics used:
+//! ### SSE2
+//! * _mm_and_si128
+//! * _mm_loadu_si128
+//! * _mm_movemask_epi8
+//! * _mm_or_si128
+//! * _mm_set1_epi8
+//! * _mm_setzero_si128
+//! * _mm_srli_epi16
+//! * _mm_subs_epu8
+//! * _mm_xor_si128
+//!
+//! ### SSSE3
+//! * _mm_alignr_epi8
+//! * _mm_shuffle_epi8
+//!
+//! ### SSE4.1
+//! * _mm_testz_si128
+use super::{BYTE_1_HIGH, BYTE_1_LOW, BYTE_2_HIGH};
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+#[inline(always)]
+fn load(bytes: &[u8]) -> __m128i {
+    debug_assert!(bytes.len() >= 16);
+    unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) }
+}
+
+#[inline(always)]
+fn lookup_16(table: &[u8; 16], indices: __m128i) -> __m128i {
+    unsafe { _mm_shuffle_epi8(load(table), indices) }
+}
+
+#[inline(always)]
+fn high_nibbles(bytes: __m128i) -> __m128i {
+    unsafe { _mm_and_si128(_mm_srli_epi16(bytes, 4), _mm_set1_epi8(0x0F)) }
+}
+
+#[inline(always)]
+fn low_nibbles(bytes: __m128i) -> __m128i {
+    unsafe { _mm_and_si128(bytes, _mm_set1_epi8(0x0F)) }
+}
+
+// The errors of each byte and the one before it.
+#[inline(always)]
+fn check_special_cases(input: __m128i, prev1: __m128i) -> __m128i {
+    unsafe {
+        let byte_1_high = lookup_16(&BYTE_1_HIGH, high_nibbles(prev1));
+        let byte_1_low = lookup_16(&BYTE_1_LOW, low_nibbles(prev1));
+        let byte_2_high = lookup_16(&BYTE_2_HIGH, high_nibbles(input));
+        _mm_and_si128(_mm_and_si128(byte_1_high, byte_1_low), byte_2_high)
+    }
+}
+
+// Two continuations in a row are only allowed as the third or fourth byte of
+// a character, which the special cases cannot tell. Flips their `TWO_CONTS`
+// bit, so that it is set exactly where a continuation is missing or
+// superfluous.
+#[inline(always)]
+fn check_multibyte_lengths(input: __m128i, prev_input: __m128i, special_cases: __m128i) -> __m128i {
+    unsafe {
+        let prev2 = _mm_alignr_epi8(input, prev_input, 16 - 2);
+        let prev3 = _mm_alignr_epi8(input, prev_input, 16 - 3);
+        // Only 111_____ will be >= 0x80
+        let is_third_byte = _mm_subs_epu8(prev2, _mm_set1_epi8((0xE0 - 0x80) as i8));
+        // Only 1111____ will be >= 0x80
+        let is_fourth_byte = _mm_subs_epu8(prev3, _mm_set1_epi8((0xF0 - 0x80) as i8));
+        let must_be_2_3_continuation = _mm_and_si128(
+            _mm_or_si128(is_third_byte, is_fourth_byte),
+            _mm_set1_epi8(0x80u8 as i8),
+        );
+        _mm_xor_si128(must_be_2_3_continuation, special_cases)
+    }
+}
+
+// Non-zero if the block ends in the middle of a character.
+#[inline(always)]
+fn is_incomplete(input: __m128i) -> __m128i {
+    unsafe { _mm_subs_epu8(input, load(&tail::INCOMPLETE_MAX[16..])) }
+}
+
+#[inline(always)]
+fn is_ascii(input: __m128i) -> bool {
+    unsafe { _mm_movemask_epi8(input) == 0 }
+}
+
+struct State {
+    prev_input: __m128i,
+    prev_incomplete: __m128i,
+    error: __m128i,
+}
+
+impl Default for State {
+    #[inline(always)]
+    fn default() -> Self {
+        unsafe {
+            State {
+                prev_input: _mm_setzero_si128(),
+                prev_incomplete: _mm_setzero_si128(),
+                error: _mm_setzero_si128(),
+            }
+        }
+    }
+}
+
+impl State {
+    #[inline(always)]
+    fn check_bytes(&mut self, input: __m128i) {
+        unsafe {
+            let prev1 = _mm_alignr_epi8(input, self.prev_input, 16 - 1);
+            let special_cases = check_special_cases(input, prev1);
+            self.error = _mm_or_si128(
+                self.error,
+                check_multibyte_lengths(input, self.prev_input, special_cases),
+            );
+            self.prev_incomplete = is_incomplete(input);
+            self.prev_input = input;
+        }
+    }
+
+    // An ASCII block is only an error if the one before it was incomplete.
+    #[inline(always)]
+    fn check_ascii(&mut self, last_input: __m128i) {
+        unsafe {
+            self.error = _mm_or_si128(self.error, self.prev_incomplete);
+            self.prev_incomplete = _mm_setzero_si128();
+            self.prev_input = last_input;
+        }
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { _mm_testz_si128(self.error, self.error) == 0 }
+    }
+
+    #[inline(always)]
+    fn is_valid(&self) -> bool {
+        unsafe {
+            let error = _mm_or_si128(self.error, self.prev_incomplete);
+            _mm_testz_si128(error, error) != 0
+        }
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut state = State::default();
+
+    // 64 bytes at a time, skipping the checks if they are all ASCII
+    let mut blocks = bytes.chunks_exact(64);
+    while blocks.len() != 0 {
+        for block in (&mut blocks).take(ERROR_CHECK_INTERVAL / 64) {
+            let input_0 = load(&block[..16]);
+            let input_1 = load(&block[16..32]);
+            let input_2 = load(&block[32..48]);
+            let input_3 = load(&block[48..]);
+            let any = _mm_or_si128(
+                _mm_or_si128(input_0, input_1),
+                _mm_or_si128(input_2, input_3),
+            );
+            if is_ascii(any) {
+                state.check_ascii(input_3);
+            } else {
+                state.check_bytes(input_0);
+                state.check_bytes(input_1);
+                state.check_bytes(input_2);
+                state.check_bytes(input_3);
+            }
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    let mut chunks = blocks.remainder().chunks_exact(16);
+    for chunk in &mut chunks {
+        state.check_bytes(load(chunk));
+    }
+    // last part
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        state.check_bytes(tail::sse(bytes, bytes.len() - rest.len()));
+    }
+
+    state.is_valid()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
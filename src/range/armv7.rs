@@ -0,0 +1,186 @@
+//! NEON implementation of the range algorithm for 32-bit ARM.
+//!
+//! ARMv7 has no 16-byte table lookup, so every `vqtbl1q_u8` of the
+//! [AArch64 version](https://github.com/cyb70289/utf8/blob/master/range-neon.c)
+//! is done as two 8-byte `vtbl2_u8` lookups. Like `vqtbl1q_u8`, they return
+//! zero for every index of 16 and above.
+//!
+//! The intrinsics are unstable, so this module needs a nightly compiler and
+//! the `armv7-neon` feature. It is only compiled if the `neon` target feature
+//! is enabled, so its functions are safe to call, and then always used.
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::arch::arm::*;
+
+// Map high nibble of "First Byte" to legal character length minus 1
+// 0x00 ~ 0xBF --> 0
+// 0xC0 ~ 0xDF --> 1
+// 0xE0 ~ 0xEF --> 2
+// 0xF0 ~ 0xFF --> 3
+static FIRST_LEN_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3];
+// Map "First Byte" to 8-th item of range table (0xC2 ~ 0xF4)
+static FIRST_RANGE_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 8, 8, 8];
+// Range table, map range index to min and max values
+// Index 0    : 00 ~ 7F (First Byte, ascii)
+// Index 1,2,3: 80 ~ BF (Second, Third, Fourth Byte)
+// Index 4    : A0 ~ BF (Second Byte after E0)
+// Index 5    : 80 ~ 9F (Second Byte after ED)
+// Index 6    : 90 ~ BF (Second Byte after F0)
+// Index 7    : 80 ~ 8F (Second Byte after F4)
+// Index 8    : C2 ~ F4 (First Byte, non ascii)
+// Index 9~15 : illegal: i >= 255 && i <= 0
+#[rustfmt::skip]
+static RANGE_MIN_TABLE: [u8; 16] = [
+    0x00, 0x80, 0x80, 0x80, 0xA0, 0x80, 0x90, 0x80,
+    0xC2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+#[rustfmt::skip]
+static RANGE_MAX_TABLE: [u8; 16] = [
+    0x7F, 0xBF, 0xBF, 0xBF, 0xBF, 0x9F, 0xBF, 0x8F,
+    0xF4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+// Range index adjustments for the four special First Bytes (E0, ED, F0, F4),
+// after which the Second Byte is not 80 ~ BF.
+// index1 -> E0, index14 -> ED
+static DF_EE_TABLE: [u8; 16] = [0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+// index1 -> F0, index5 -> F4
+static EF_FE_TABLE: [u8; 16] = [0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+#[inline(always)]
+fn load_table(table: &[u8; 16]) -> uint8x8x2_t {
+    unsafe { uint8x8x2_t(vld1_u8(table.as_ptr()), vld1_u8(table.as_ptr().add(8))) }
+}
+
+// 16-byte table lookup, out of range indices give zero
+#[inline(always)]
+fn lookup(table: uint8x8x2_t, indices: uint8x16_t) -> uint8x16_t {
+    unsafe {
+        vcombine_u8(
+            vtbl2_u8(table, vget_low_u8(indices)),
+            vtbl2_u8(table, vget_high_u8(indices)),
+        )
+    }
+}
+
+#[inline(always)]
+fn is_zero(v: uint8x16_t) -> bool {
+    unsafe {
+        let v = vreinterpretq_u64_u8(v);
+        (vgetq_lane_u64(v, 0) | vgetq_lane_u64(v, 1)) == 0
+    }
+}
+
+// Checks the 16 bytes of `input`, which follow `prev_input`. Returns the
+// errors found and the lengths of the characters that start in `input`.
+#[inline(always)]
+fn check_block(
+    input: uint8x16_t,
+    prev_input: uint8x16_t,
+    prev_first_len: uint8x16_t,
+) -> (uint8x16_t, uint8x16_t) {
+    unsafe {
+        let first_len_tbl = load_table(&FIRST_LEN_TABLE);
+        let first_range_tbl = load_table(&FIRST_RANGE_TABLE);
+        let range_min_tbl = load_table(&RANGE_MIN_TABLE);
+        let range_max_tbl = load_table(&RANGE_MAX_TABLE);
+        let df_ee_tbl = load_table(&DF_EE_TABLE);
+        let ef_fe_tbl = load_table(&EF_FE_TABLE);
+
+        /* high_nibbles = input >> 4 */
+        let high_nibbles = vshrq_n_u8(input, 4);
+        /* first_len = legal character length minus 1 */
+        /* 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF */
+        let first_len = lookup(first_len_tbl, high_nibbles);
+        /* First Byte: set range index to 8 for bytes within 0xC0 ~ 0xFF */
+        let mut range = lookup(first_range_tbl, high_nibbles);
+        /* Second Byte: set range index to first_len */
+        /* range |= (first_len, prev_first_len) << 1 byte */
+        range = vorrq_u8(range, vextq_u8(prev_first_len, first_len, 15));
+        /* Third Byte: set range index to saturate_sub(first_len, 1) */
+        let mut tmp1 = vqsubq_u8(first_len, vdupq_n_u8(1));
+        let mut tmp2 = vqsubq_u8(prev_first_len, vdupq_n_u8(1));
+        /* range |= (tmp1, tmp2) << 2 bytes */
+        range = vorrq_u8(range, vextq_u8(tmp2, tmp1, 14));
+        /* Fourth Byte: set range index to saturate_sub(first_len, 2) */
+        tmp1 = vqsubq_u8(first_len, vdupq_n_u8(2));
+        tmp2 = vqsubq_u8(prev_first_len, vdupq_n_u8(2));
+        /* range |= (tmp1, tmp2) << 3 bytes */
+        range = vorrq_u8(range, vextq_u8(tmp2, tmp1, 13));
+        /*
+         * Adjust Second Byte range for special First Bytes(E0,ED,F0,F4)
+         * Overlaps lead to index 9~15, which are illegal in range table
+         */
+        let shift1 = vextq_u8(prev_input, input, 15);
+        let pos = vsubq_u8(shift1, vdupq_n_u8(0xEF));
+        /*
+         * shift1:  | EF  F0 ... FE | FF  00  ... ...  DE | DF  E0 ... EE |
+         * pos:     | 0   1      15 | 16  17           239| 240 241    255|
+         * pos-240: | 0   0      0  | 0   0            0  | 0   1      15 |
+         */
+        tmp1 = vqsubq_u8(pos, vdupq_n_u8(240));
+        let mut range2 = lookup(df_ee_tbl, tmp1);
+        /* Indices of 16 and above look up zero */
+        range2 = vaddq_u8(range2, lookup(ef_fe_tbl, pos));
+        range = vaddq_u8(range, range2);
+        /* Load min and max values per calculated range index */
+        let minv = lookup(range_min_tbl, range);
+        let maxv = lookup(range_max_tbl, range);
+        /* Check value range */
+        let error = vorrq_u8(vcltq_u8(input, minv), vcgtq_u8(input, maxv));
+        (error, first_len)
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut data = bytes.as_ptr();
+    let mut len = bytes.len();
+    unsafe {
+        let mut prev_input = vdupq_n_u8(0);
+        let mut prev_first_len = vdupq_n_u8(0);
+        let mut error = vdupq_n_u8(0);
+
+        while len >= 16 {
+            let stop = len.saturating_sub(ERROR_CHECK_INTERVAL).max(15);
+            while len > stop {
+                let input = vld1q_u8(data);
+                let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+                error = vorrq_u8(error, block_error);
+
+                prev_input = input;
+                prev_first_len = first_len;
+                data = data.add(16);
+                len -= 16;
+            }
+            if !is_zero(error) {
+                return false;
+            }
+        }
+        // last part
+        if len > 0 {
+            let input = tail::armv7(bytes, bytes.len() - len);
+            let (block_error, _) = check_block(input, prev_input, prev_first_len);
+            error = vorrq_u8(error, block_error);
+            prev_input = input;
+        }
+        let incomplete_max = vld1q_u8(tail::INCOMPLETE_MAX[16..].as_ptr());
+        error = vorrq_u8(error, vqsubq_u8(prev_input, incomplete_max));
+        is_zero(error)
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
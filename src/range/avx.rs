@@ -1,4 +1,4 @@
-use crate::libcore;
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
 #[cfg(target_arch = "x86")]
 use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
@@ -67,7 +67,7 @@ static RANGE_MAX_TABLE: [i8; 32] = [
     0xBFi32 as i8,
     0x9Fi32 as i8,
     0xBFi32 as i8,
-    0x8Fi32 as i8,
+    0x8fi32 as i8,
     0xF4i32 as i8,
     0x80i32 as i8,
     0x80i32 as i8,
@@ -118,19 +118,19 @@ static EF_FE_TABLE: [i8; 32] = [
 ];
 
 /* Define 1 to return index of first error char */
-#[inline]
+#[inline(always)]
 fn push_last_byte_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
     unsafe {
         return _mm256_alignr_epi8(b, _mm256_permute2x128_si256(a, b, 0x21), 15);
     }
 }
-#[inline]
+#[inline(always)]
 fn push_last_2bytes_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
     unsafe {
         return _mm256_alignr_epi8(b, _mm256_permute2x128_si256(a, b, 0x21), 14);
     }
 }
-#[inline]
+#[inline(always)]
 fn push_last_3bytes_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
     unsafe {
         return _mm256_alignr_epi8(b, _mm256_permute2x128_si256(a, b, 0x21), 13);
@@ -138,113 +138,175 @@ fn push_last_3bytes_of_a_to_b(a: __m256i, b: __m256i) -> __m256i {
 }
 
 /* 5x faster than naive method */
-/* Return 0 - success, -1 - error, >0 - first error char(if RET_ERR_IDX = 1) */
-pub fn is_utf8(bytes: &[u8]) -> bool {
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    check_utf8::<false>(bytes)
+}
+
+/// Returns `true` if `bytes` is valid UTF-8, skipping the full check for
+/// blocks that only contain ASCII.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+    check_utf8::<true>(bytes)
+}
+
+// Checks the 32 bytes of `input`, which follow `prev_input`. Returns the
+// errors found and the lengths of the characters that start in `input`, which
+// the next block needs.
+#[inline(always)]
+fn check_block(input: __m256i, prev_input: __m256i, prev_first_len: __m256i) -> (__m256i, __m256i) {
+    unsafe {
+        // This should be safe as long as the tables are all 32 bytes long
+        let first_len_tbl = _mm256_lddqu_si256(FIRST_LEN_TABLE.as_ptr() as *const __m256i);
+        let first_range_tbl = _mm256_lddqu_si256(FIRST_RANGE_TABLE.as_ptr() as *const __m256i);
+        let range_min_tbl = _mm256_lddqu_si256(RANGE_MIN_TABLE.as_ptr() as *const __m256i);
+        let range_max_tbl = _mm256_lddqu_si256(RANGE_MAX_TABLE.as_ptr() as *const __m256i);
+        let df_ee_tbl = _mm256_lddqu_si256(DF_EE_TABLE.as_ptr() as *const __m256i);
+        let ef_fe_tbl = _mm256_lddqu_si256(EF_FE_TABLE.as_ptr() as *const __m256i);
+
+        /* high_nibbles = input >> 4 */
+        let high_nibbles = _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0xF));
+        /* first_len = legal character length minus 1 */
+        /* 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF */
+        /* first_len = first_len_tbl[high_nibbles] */
+        let first_len = _mm256_shuffle_epi8(first_len_tbl, high_nibbles);
+        // First Byte: set range index to 8 for bytes within 0xC0 ~ 0xFF
+        /* range = first_range_tbl[high_nibbles] */
+        let mut range = _mm256_shuffle_epi8(first_range_tbl, high_nibbles);
+        // Second Byte: set range index to first_len
+        // 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF
+        /* range |= (first_len, prev_first_len) << 1 byte */
+        range = _mm256_or_si256(range, push_last_byte_of_a_to_b(prev_first_len, first_len));
+        // Third Byte: set range index to saturate_sub(first_len, 1)
+        // 0 for 00~7F, 0 for C0~DF, 1 for E0~EF, 2 for F0~FF
+        /* tmp1 = saturate_sub(first_len, 1) */
+        let mut tmp1 = _mm256_subs_epu8(first_len, _mm256_set1_epi8(1));
+        /* tmp2 = saturate_sub(prev_first_len, 1) */
+        let mut tmp2 = _mm256_subs_epu8(prev_first_len, _mm256_set1_epi8(1));
+        /* range |= (tmp1, tmp2) << 2 bytes */
+        range = _mm256_or_si256(range, push_last_2bytes_of_a_to_b(tmp2, tmp1));
+        // Fourth Byte: set range index to saturate_sub(first_len, 2)
+        // 0 for 00~7F, 0 for C0~DF, 0 for E0~EF, 1 for F0~FF
+        /* tmp1 = saturate_sub(first_len, 2) */
+        tmp1 = _mm256_subs_epu8(first_len, _mm256_set1_epi8(2));
+        /* tmp2 = saturate_sub(prev_first_len, 2) */
+        tmp2 = _mm256_subs_epu8(prev_first_len, _mm256_set1_epi8(2));
+        /* range |= (tmp1, tmp2) << 3 bytes */
+        range = _mm256_or_si256(range, push_last_3bytes_of_a_to_b(tmp2, tmp1));
+        // Now we have below range indices caluclated
+        // Correct cases:
+        // - 8 for C0~FF
+        // - 3 for 1st byte after F0~FF
+        // - 2 for 1st byte after E0~EF or 2nd byte after F0~FF
+        // - 1 for 1st byte after C0~DF or 2nd byte after E0~EF or
+        //         3rd byte after F0~FF
+        // - 0 for others
+        // Error cases:
+        //   9,10,11 if non ascii First Byte overlaps
+        //   E.g., F1 80 C2 90 --> 8 3 10 2, where 10 indicates error
+        //
+        // Adjust Second Byte range for special First Bytes(E0,ED,F0,F4)
+        // Overlaps lead to index 9~15, which are illegal in range table
+        // shift1 = (input, prev_input) << 1 byte
+        let shift1 = push_last_byte_of_a_to_b(prev_input, input);
+        let pos = _mm256_sub_epi8(shift1, _mm256_set1_epi8(0xEFi32 as i8));
+        // ---------+---------------+---------------------+---------------+
+        // shift1:  | EF  F0 ... FE | FF  00  ... ...  DE | DF  E0 ... EE |
+        // pos:     | 0   1      15 | 16  17           239| 240 241    255|
+        // pos-240: | 0   0      0  | 0   0            0  | 0   1      15 |
+        // pos+112: | 112 113    127|       >= 128        |     >= 128    |
+        // ---------+---------------+---------------------+---------------+
+        tmp1 = _mm256_subs_epu8(pos, _mm256_set1_epi8(240i32 as i8));
+        let mut range2 = _mm256_shuffle_epi8(df_ee_tbl, tmp1);
+        tmp2 = _mm256_adds_epu8(pos, _mm256_set1_epi8(112i32 as i8));
+        range2 = _mm256_add_epi8(range2, _mm256_shuffle_epi8(ef_fe_tbl, tmp2));
+        range = _mm256_add_epi8(range, range2);
+        // Load min and max values per calculated range index
+        let minv = _mm256_shuffle_epi8(range_min_tbl, range);
+        let maxv = _mm256_shuffle_epi8(range_max_tbl, range);
+        // Check value range
+        let error = _mm256_or_si256(
+            _mm256_cmpgt_epi8(minv, input),
+            _mm256_cmpgt_epi8(input, maxv),
+        );
+        (error, first_len)
+    }
+}
+
+#[inline(always)]
+unsafe fn check_utf8<const ASCII_PATH: bool>(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
     let mut data = bytes.as_ptr();
     let mut len = bytes.len();
     unsafe {
-        if len >= 32 {
-            let mut prev_input = _mm256_setzero_si256();
-            let mut prev_first_len = _mm256_setzero_si256();
-            let mut error = _mm256_setzero_si256();
+        let mut prev_input = _mm256_setzero_si256();
+        let mut prev_first_len = _mm256_setzero_si256();
+        let mut error = _mm256_setzero_si256();
+        let incomplete_max = _mm256_lddqu_si256(tail::INCOMPLETE_MAX.as_ptr() as *const __m256i);
 
-            // This should be safe as long as the tables are all 32 bytes long
-            let first_len_tbl = _mm256_lddqu_si256(FIRST_LEN_TABLE.as_ptr() as *const __m256i);
-            let first_range_tbl = _mm256_lddqu_si256(FIRST_RANGE_TABLE.as_ptr() as *const __m256i);
-            let range_min_tbl = _mm256_lddqu_si256(RANGE_MIN_TABLE.as_ptr() as *const __m256i);
-            let range_max_tbl = _mm256_lddqu_si256(RANGE_MAX_TABLE.as_ptr() as *const __m256i);
-            let df_ee_tbl = _mm256_lddqu_si256(DF_EE_TABLE.as_ptr() as *const __m256i);
-            let ef_fe_tbl = _mm256_lddqu_si256(EF_FE_TABLE.as_ptr() as *const __m256i);
-
-            while len >= 32 {
+        while len >= 32 {
+            // A fixed number of blocks, so that the inner loop only counts.
+            let blocks = (len / 32).min(ERROR_CHECK_INTERVAL / 32);
+            for _ in 0..blocks {
                 let input = _mm256_lddqu_si256(data as *const __m256i);
-                /* high_nibbles = input >> 4 */
-                let high_nibbles =
-                    _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0xF));
-                /* first_len = legal character length minus 1 */
-                /* 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF */
-                /* first_len = first_len_tbl[high_nibbles] */
-                let first_len = _mm256_shuffle_epi8(first_len_tbl, high_nibbles);
-                // First Byte: set range index to 8 for bytes within 0xC0 ~ 0xFF
-                /* range = first_range_tbl[high_nibbles] */
-                let mut range = _mm256_shuffle_epi8(first_range_tbl, high_nibbles);
-                // Second Byte: set range index to first_len
-                // 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF
-                /* range |= (first_len, prev_first_len) << 1 byte */
-                range = _mm256_or_si256(range, push_last_byte_of_a_to_b(prev_first_len, first_len));
-                // Third Byte: set range index to saturate_sub(first_len, 1)
-                // 0 for 00~7F, 0 for C0~DF, 1 for E0~EF, 2 for F0~FF
-                /* tmp1 = saturate_sub(first_len, 1) */
-                let mut tmp1 = _mm256_subs_epu8(first_len, _mm256_set1_epi8(1));
-                /* tmp2 = saturate_sub(prev_first_len, 1) */
-                let mut tmp2 = _mm256_subs_epu8(prev_first_len, _mm256_set1_epi8(1));
-                /* range |= (tmp1, tmp2) << 2 bytes */
-                range = _mm256_or_si256(range, push_last_2bytes_of_a_to_b(tmp2, tmp1));
-                // Fourth Byte: set range index to saturate_sub(first_len, 2)
-                // 0 for 00~7F, 0 for C0~DF, 0 for E0~EF, 1 for F0~FF
-                /* tmp1 = saturate_sub(first_len, 2) */
-                tmp1 = _mm256_subs_epu8(first_len, _mm256_set1_epi8(2));
-                /* tmp2 = saturate_sub(prev_first_len, 2) */
-                tmp2 = _mm256_subs_epu8(prev_first_len, _mm256_set1_epi8(2));
-                /* range |= (tmp1, tmp2) << 3 bytes */
-                range = _mm256_or_si256(range, push_last_3bytes_of_a_to_b(tmp2, tmp1));
-                // Now we have below range indices caluclated
-                // Correct cases:
-                // - 8 for C0~FF
-                // - 3 for 1st byte after F0~FF
-                // - 2 for 1st byte after E0~EF or 2nd byte after F0~FF
-                // - 1 for 1st byte after C0~DF or 2nd byte after E0~EF or
-                //         3rd byte after F0~FF
-                // - 0 for others
-                // Error cases:
-                //   9,10,11 if non ascii First Byte overlaps
-                //   E.g., F1 80 C2 90 --> 8 3 10 2, where 10 indicates error
-                //
-                // Adjust Second Byte range for special First Bytes(E0,ED,F0,F4)
-                // Overlaps lead to index 9~15, which are illegal in range table
-                // shift1 = (input, prev_input) << 1 byte
-                let shift1 = push_last_byte_of_a_to_b(prev_input, input);
-                let pos = _mm256_sub_epi8(shift1, _mm256_set1_epi8(0xEFi32 as i8));
-                // ---------+---------------+---------------------+---------------+
-                // shift1:  | EF  F0 ... FE | FF  00  ... ...  DE | DF  E0 ... EE |
-                // pos:     | 0   1      15 | 16  17           239| 240 241    255|
-                // pos-240: | 0   0      0  | 0   0            0  | 0   1      15 |
-                // pos+112: | 112 113    127|       >= 128        |     >= 128    |
-                // ---------+---------------+---------------------+---------------+
-                tmp1 = _mm256_subs_epu8(pos, _mm256_set1_epi8(240i32 as i8));
-                let mut range2 = _mm256_shuffle_epi8(df_ee_tbl, tmp1);
-                tmp2 = _mm256_adds_epu8(pos, _mm256_set1_epi8(112i32 as i8));
-                range2 = _mm256_add_epi8(range2, _mm256_shuffle_epi8(ef_fe_tbl, tmp2));
-                range = _mm256_add_epi8(range, range2);
-                // Load min and max values per calculated range index
-                let minv = _mm256_shuffle_epi8(range_min_tbl, range);
-                let maxv = _mm256_shuffle_epi8(range_max_tbl, range);
-                // Check value range
-                error = _mm256_or_si256(error, _mm256_cmpgt_epi8(minv, input));
-                error = _mm256_or_si256(error, _mm256_cmpgt_epi8(input, maxv));
-                prev_input = input;
-                prev_first_len = first_len;
+                if ASCII_PATH && _mm256_testz_si256(input, _mm256_set1_epi8(0x80u8 as i8)) != 0 {
+                    // An ASCII block is only an error if the one before it
+                    // was incomplete. After it, nothing is carried over,
+                    // just as if it were zeros.
+                    error = _mm256_or_si256(error, _mm256_subs_epu8(prev_input, incomplete_max));
+                    prev_input = _mm256_setzero_si256();
+                    prev_first_len = _mm256_setzero_si256();
+                } else {
+                    let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+                    error = _mm256_or_si256(error, block_error);
+                    prev_input = input;
+                    prev_first_len = first_len;
+                }
                 data = data.offset(32);
-                len -= 32
             }
+            len -= blocks * 32;
             if _mm256_testz_si256(error, error) == 0 {
                 return false;
             }
-            // Find previous token (not 80~BF)
-            let mut token4: i32 = _mm256_extract_epi32(prev_input, 7);
-            let token: *const i8 = &mut token4 as *mut i32 as *const i8;
-            let mut lookahead = 0;
-            if *token.offset(3) > 0xBFi32 as i8 {
-                lookahead = 1
-            } else if *token.offset(2) > 0xBFi32 as i8 {
-                lookahead = 2
-            } else if *token.offset(1) > 0xBFi32 as i8 {
-                lookahead = 3
-            }
-            data = data.offset(-(lookahead as isize));
-            len += lookahead
         }
-        // Check remaining bytes with naive method
-        return libcore::is_utf8(core::slice::from_raw_parts(data, len));
+        // last part, at most 31 bytes
+        if len > 0 {
+            let input = tail::avx(bytes, bytes.len() - len);
+            let (block_error, _) = check_block(input, prev_input, prev_first_len);
+            error = _mm256_or_si256(error, block_error);
+            prev_input = input;
+        }
+        error = _mm256_or_si256(error, _mm256_subs_epu8(prev_input, incomplete_max));
+        _mm256_testz_si256(error, error) != 0
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support AVX and AVX2.
+#[target_feature(enable = "avx,avx2")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
     }
 }
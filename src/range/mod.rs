@@ -7,9 +7,40 @@
 //! This module is expertimental. There may be breaking API changes.
 //!
 //! [1]: https://github.com/cyb70289/utf8
-#[cfg(any(all(target_feature = "avx", target_feature = "avx2"), dox))]
-#[cfg_attr(dox, doc(cfg(all(target_feature = "avx", target_feature = "avx2"))))]
+#[cfg(all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"))]
+#[cfg_attr(
+    dox,
+    doc(cfg(all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon")))
+)]
+pub mod armv7;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
 pub mod avx;
-#[cfg(any(all(target_feature = "sse4.1"), dox))]
-#[cfg_attr(dox, doc(cfg(all(target_feature = "sse4.1"))))]
+#[cfg(target_arch = "aarch64")]
+#[cfg_attr(dox, doc(cfg(target_arch = "aarch64")))]
+pub mod neon;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[cfg_attr(dox, doc(cfg(all(target_arch = "wasm32", target_feature = "simd128"))))]
+pub mod simd128;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
 pub mod sse;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg_attr(dox, doc(cfg(any(target_arch = "x86", target_arch = "x86_64"))))]
+pub mod sse2;
+#[cfg(all(
+    feature = "powerpc-vsx",
+    target_arch = "powerpc64",
+    target_endian = "little",
+    target_feature = "vsx"
+))]
+#[cfg_attr(
+    dox,
+    doc(cfg(all(
+        feature = "powerpc-vsx",
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_feature = "vsx"
+    )))
+)]
+pub mod vsx;
@@ -0,0 +1,178 @@
+//! WebAssembly SIMD implementation of the range algorithm.
+//!
+//! `u8x16_swizzle` returns zero for every index of 16 and above, and
+//! WebAssembly can compare unsigned bytes directly, so this follows the NEON
+//! version rather than the SSE one.
+//!
+//! WebAssembly has no runtime feature detection. This module is only
+//! compiled if the `simd128` target feature is enabled, so its functions are
+//! safe to call.
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::arch::wasm32::*;
+
+// Map high nibble of "First Byte" to legal character length minus 1
+// 0x00 ~ 0xBF --> 0
+// 0xC0 ~ 0xDF --> 1
+// 0xE0 ~ 0xEF --> 2
+// 0xF0 ~ 0xFF --> 3
+static FIRST_LEN_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3];
+// Map "First Byte" to 8-th item of range table (0xC2 ~ 0xF4)
+static FIRST_RANGE_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 8, 8, 8];
+// Range table, map range index to min and max values
+// Index 0    : 00 ~ 7F (First Byte, ascii)
+// Index 1,2,3: 80 ~ BF (Second, Third, Fourth Byte)
+// Index 4    : A0 ~ BF (Second Byte after E0)
+// Index 5    : 80 ~ 9F (Second Byte after ED)
+// Index 6    : 90 ~ BF (Second Byte after F0)
+// Index 7    : 80 ~ 8F (Second Byte after F4)
+// Index 8    : C2 ~ F4 (First Byte, non ascii)
+// Index 9~15 : illegal: i >= 255 && i <= 0
+#[rustfmt::skip]
+static RANGE_MIN_TABLE: [u8; 16] = [
+    0x00, 0x80, 0x80, 0x80, 0xA0, 0x80, 0x90, 0x80,
+    0xC2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+#[rustfmt::skip]
+static RANGE_MAX_TABLE: [u8; 16] = [
+    0x7F, 0xBF, 0xBF, 0xBF, 0xBF, 0x9F, 0xBF, 0x8F,
+    0xF4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+// Range index adjustments for the four special First Bytes (E0, ED, F0, F4),
+// after which the Second Byte is not 80 ~ BF.
+// index1 -> E0, index14 -> ED
+static DF_EE_TABLE: [u8; 16] = [0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+// index1 -> F0, index5 -> F4
+static EF_FE_TABLE: [u8; 16] = [0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+#[inline(always)]
+fn load_table(table: &[u8; 16]) -> v128 {
+    unsafe { v128_load(table.as_ptr() as *const v128) }
+}
+
+// (a, b) << 1 byte
+#[inline(always)]
+fn prev1(a: v128, b: v128) -> v128 {
+    u8x16_shuffle::<15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30>(a, b)
+}
+
+// (a, b) << 2 bytes
+#[inline(always)]
+fn prev2(a: v128, b: v128) -> v128 {
+    u8x16_shuffle::<14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29>(a, b)
+}
+
+// (a, b) << 3 bytes
+#[inline(always)]
+fn prev3(a: v128, b: v128) -> v128 {
+    u8x16_shuffle::<13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28>(a, b)
+}
+
+// Checks the 16 bytes of `input`, which follow `prev_input`. Returns the
+// errors found and the lengths of the characters that start in `input`.
+#[inline(always)]
+fn check_block(input: v128, prev_input: v128, prev_first_len: v128) -> (v128, v128) {
+    let first_len_tbl = load_table(&FIRST_LEN_TABLE);
+    let first_range_tbl = load_table(&FIRST_RANGE_TABLE);
+    let range_min_tbl = load_table(&RANGE_MIN_TABLE);
+    let range_max_tbl = load_table(&RANGE_MAX_TABLE);
+    let df_ee_tbl = load_table(&DF_EE_TABLE);
+    let ef_fe_tbl = load_table(&EF_FE_TABLE);
+
+    /* high_nibbles = input >> 4 */
+    let high_nibbles = u8x16_shr(input, 4);
+    /* first_len = legal character length minus 1 */
+    /* 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF */
+    let first_len = u8x16_swizzle(first_len_tbl, high_nibbles);
+    /* First Byte: set range index to 8 for bytes within 0xC0 ~ 0xFF */
+    let mut range = u8x16_swizzle(first_range_tbl, high_nibbles);
+    /* Second Byte: set range index to first_len */
+    /* range |= (first_len, prev_first_len) << 1 byte */
+    range = v128_or(range, prev1(prev_first_len, first_len));
+    /* Third Byte: set range index to saturate_sub(first_len, 1) */
+    let mut tmp1 = u8x16_sub_sat(first_len, u8x16_splat(1));
+    let mut tmp2 = u8x16_sub_sat(prev_first_len, u8x16_splat(1));
+    /* range |= (tmp1, tmp2) << 2 bytes */
+    range = v128_or(range, prev2(tmp2, tmp1));
+    /* Fourth Byte: set range index to saturate_sub(first_len, 2) */
+    tmp1 = u8x16_sub_sat(first_len, u8x16_splat(2));
+    tmp2 = u8x16_sub_sat(prev_first_len, u8x16_splat(2));
+    /* range |= (tmp1, tmp2) << 3 bytes */
+    range = v128_or(range, prev3(tmp2, tmp1));
+    /*
+     * Adjust Second Byte range for special First Bytes(E0,ED,F0,F4)
+     * Overlaps lead to index 9~15, which are illegal in range table
+     */
+    let shift1 = prev1(prev_input, input);
+    let pos = u8x16_sub(shift1, u8x16_splat(0xEF));
+    /*
+     * shift1:  | EF  F0 ... FE | FF  00  ... ...  DE | DF  E0 ... EE |
+     * pos:     | 0   1      15 | 16  17           239| 240 241    255|
+     * pos-240: | 0   0      0  | 0   0            0  | 0   1      15 |
+     */
+    tmp1 = u8x16_sub_sat(pos, u8x16_splat(240));
+    let mut range2 = u8x16_swizzle(df_ee_tbl, tmp1);
+    /* Indices of 16 and above look up zero */
+    range2 = u8x16_add(range2, u8x16_swizzle(ef_fe_tbl, pos));
+    range = u8x16_add(range, range2);
+    /* Load min and max values per calculated range index */
+    let minv = u8x16_swizzle(range_min_tbl, range);
+    let maxv = u8x16_swizzle(range_max_tbl, range);
+    /* Check value range */
+    let error = v128_or(u8x16_lt(input, minv), u8x16_gt(input, maxv));
+    (error, first_len)
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut data = bytes.as_ptr();
+    let mut len = bytes.len();
+    unsafe {
+        let mut prev_input = u8x16_splat(0);
+        let mut prev_first_len = u8x16_splat(0);
+        let mut error = u8x16_splat(0);
+
+        while len >= 16 {
+            let stop = len.saturating_sub(ERROR_CHECK_INTERVAL).max(15);
+            while len > stop {
+                let input = v128_load(data as *const v128);
+                let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+                error = v128_or(error, block_error);
+
+                prev_input = input;
+                prev_first_len = first_len;
+                data = data.add(16);
+                len -= 16;
+            }
+            if v128_any_true(error) {
+                return false;
+            }
+        }
+        // last part
+        if len > 0 {
+            let input = tail::simd128(bytes, bytes.len() - len);
+            let (block_error, _) = check_block(input, prev_input, prev_first_len);
+            error = v128_or(error, block_error);
+            prev_input = input;
+        }
+        let incomplete_max = v128_load(tail::INCOMPLETE_MAX[16..].as_ptr() as *const v128);
+        error = v128_or(error, u8x16_sub_sat(prev_input, incomplete_max));
+        !v128_any_true(error)
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -7,7 +7,7 @@ use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 
-use crate::libcore;
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
 
 static FIRST_LEN_TABLE: [i8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3];
 static FIRST_RANGE_TABLE: [i8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 8, 8, 8];
@@ -50,93 +50,146 @@ static RANGE_MAX_TABLE: [i8; 16] = [
 static DF_EE_TABLE: [i8; 16] = [0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
 static EF_FE_TABLE: [i8; 16] = [0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
 
-pub fn is_utf8(bytes: &[u8]) -> bool {
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    check_utf8::<false>(bytes)
+}
+
+/// Returns `true` if `bytes` is valid UTF-8, skipping the full check for
+/// blocks that only contain ASCII.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn is_utf8_ascii_path(bytes: &[u8]) -> bool {
+    check_utf8::<true>(bytes)
+}
+
+// Checks the 16 bytes of `input`, which follow `prev_input`. Returns the
+// errors found and the lengths of the characters that start in `input`, which
+// the next block needs.
+#[inline(always)]
+fn check_block(input: __m128i, prev_input: __m128i, prev_first_len: __m128i) -> (__m128i, __m128i) {
+    unsafe {
+        // This should be safe as long as the tables are all 16 bytes long
+        let first_len_table = _mm_lddqu_si128(FIRST_LEN_TABLE.as_ptr() as *const __m128i);
+        let first_range_table = _mm_lddqu_si128(FIRST_RANGE_TABLE.as_ptr() as *const __m128i);
+        let range_min_table = _mm_lddqu_si128(RANGE_MIN_TABLE.as_ptr() as *const __m128i);
+        let range_max_table = _mm_lddqu_si128(RANGE_MAX_TABLE.as_ptr() as *const __m128i);
+        let df_ee_table = _mm_lddqu_si128(DF_EE_TABLE.as_ptr() as *const __m128i);
+        let ef_fe_table = _mm_lddqu_si128(EF_FE_TABLE.as_ptr() as *const __m128i);
+
+        let high_nibbles = _mm_and_si128(_mm_srli_epi16(input, 4), _mm_set1_epi8(0xF));
+        let first_len = _mm_shuffle_epi8(first_len_table, high_nibbles);
+        let mut range = _mm_shuffle_epi8(first_range_table, high_nibbles);
+        range = _mm_or_si128(range, _mm_alignr_epi8(first_len, prev_first_len, 15));
+        let mut tmp1 = _mm_subs_epu8(first_len, _mm_set1_epi8(1));
+        let mut tmp2 = _mm_subs_epu8(prev_first_len, _mm_set1_epi8(1));
+        range = _mm_or_si128(range, _mm_alignr_epi8(tmp1, tmp2, 14));
+        tmp1 = _mm_subs_epu8(first_len, _mm_set1_epi8(2));
+        tmp2 = _mm_subs_epu8(prev_first_len, _mm_set1_epi8(2));
+        range = _mm_or_si128(range, _mm_alignr_epi8(tmp1, tmp2, 13));
+        let shift1 = _mm_alignr_epi8(input, prev_input, 15);
+        let pos = _mm_sub_epi8(shift1, _mm_set1_epi8(0xEFi32 as i8));
+        tmp1 = _mm_subs_epu8(pos, _mm_set1_epi8(240i32 as i8));
+        let mut range2 = _mm_shuffle_epi8(df_ee_table, tmp1);
+        tmp2 = _mm_adds_epu8(pos, _mm_set1_epi8(112));
+        range2 = _mm_add_epi8(range2, _mm_shuffle_epi8(ef_fe_table, tmp2));
+        range = _mm_add_epi8(range, range2);
+        let minv = _mm_shuffle_epi8(range_min_table, range);
+        let maxv = _mm_shuffle_epi8(range_max_table, range);
+        let error = _mm_or_si128(_mm_cmplt_epi8(input, minv), _mm_cmpgt_epi8(input, maxv));
+        (error, first_len)
+    }
+}
+
+#[inline(always)]
+unsafe fn check_utf8<const ASCII_PATH: bool>(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
     let mut data = bytes.as_ptr();
     let mut len = bytes.len();
     unsafe {
-        if len >= 32 {
-            let mut prev_input = _mm_setzero_si128();
-            let mut prev_first_len = _mm_setzero_si128();
-            let mut error = _mm_setzero_si128();
-
-            // This should be safe as long as the tables are all 16 bytes long
-            let first_len_table = _mm_lddqu_si128(FIRST_LEN_TABLE.as_ptr() as *const __m128i);
-            let first_range_table = _mm_lddqu_si128(FIRST_RANGE_TABLE.as_ptr() as *const __m128i);
-            let range_min_table = _mm_lddqu_si128(RANGE_MIN_TABLE.as_ptr() as *const __m128i);
-            let range_max_table = _mm_lddqu_si128(RANGE_MAX_TABLE.as_ptr() as *const __m128i);
-            let df_ee_table = _mm_lddqu_si128(DF_EE_TABLE.as_ptr() as *const __m128i);
-            let ef_fe_table = _mm_lddqu_si128(EF_FE_TABLE.as_ptr() as *const __m128i);
+        let mut prev_input = _mm_setzero_si128();
+        let mut prev_first_len = _mm_setzero_si128();
+        let mut error = _mm_setzero_si128();
+        let incomplete_max = _mm_lddqu_si128(tail::INCOMPLETE_MAX[16..].as_ptr() as *const __m128i);
 
-            while len >= 32 {
-                /* **************************** block 1 ****************************/
+        while len >= 32 {
+            let stop = len.saturating_sub(ERROR_CHECK_INTERVAL).max(31);
+            while len > stop {
                 let input = _mm_lddqu_si128(data as *const __m128i);
-                let mut high_nibbles = _mm_and_si128(_mm_srli_epi16(input, 4), _mm_set1_epi8(0xF));
-                let first_len = _mm_shuffle_epi8(first_len_table, high_nibbles);
-                let mut range = _mm_shuffle_epi8(first_range_table, high_nibbles);
-                range = _mm_or_si128(range, _mm_alignr_epi8(first_len, prev_first_len, 15));
-                let mut tmp1 = _mm_subs_epu8(first_len, _mm_set1_epi8(1));
-                let mut tmp2 = _mm_subs_epu8(prev_first_len, _mm_set1_epi8(1));
-                range = _mm_or_si128(range, _mm_alignr_epi8(tmp1, tmp2, 14));
-                tmp1 = _mm_subs_epu8(first_len, _mm_set1_epi8(2));
-                tmp2 = _mm_subs_epu8(prev_first_len, _mm_set1_epi8(2));
-                range = _mm_or_si128(range, _mm_alignr_epi8(tmp1, tmp2, 13));
-                let mut shift1 = _mm_alignr_epi8(input, prev_input, 15);
-                let mut pos = _mm_sub_epi8(shift1, _mm_set1_epi8(0xEFi32 as i8));
-                tmp1 = _mm_subs_epu8(pos, _mm_set1_epi8(240i32 as i8));
-                let mut range2 = _mm_shuffle_epi8(df_ee_table, tmp1);
-                tmp2 = _mm_adds_epu8(pos, _mm_set1_epi8(112));
-                range2 = _mm_add_epi8(range2, _mm_shuffle_epi8(ef_fe_table, tmp2));
-                range = _mm_add_epi8(range, range2);
-                let mut minv: __m128i = _mm_shuffle_epi8(range_min_table, range);
-                let mut maxv: __m128i = _mm_shuffle_epi8(range_max_table, range);
-                error = _mm_or_si128(error, _mm_cmplt_epi8(input, minv));
-                error = _mm_or_si128(error, _mm_cmpgt_epi8(input, maxv));
-                /* **************************** block 2 ****************************/
-                let _input = _mm_lddqu_si128(data.offset(16 as isize) as *const __m128i);
-                high_nibbles = _mm_and_si128(_mm_srli_epi16(_input, 4), _mm_set1_epi8(0xF));
-                let mut _first_len = _mm_shuffle_epi8(first_len_table, high_nibbles);
-                let mut _range = _mm_shuffle_epi8(first_range_table, high_nibbles);
-                _range = _mm_or_si128(_range, _mm_alignr_epi8(_first_len, first_len, 15));
-                tmp1 = _mm_subs_epu8(_first_len, _mm_set1_epi8(1));
-                tmp2 = _mm_subs_epu8(first_len, _mm_set1_epi8(1));
-                _range = _mm_or_si128(_range, _mm_alignr_epi8(tmp1, tmp2, 14));
-                tmp1 = _mm_subs_epu8(_first_len, _mm_set1_epi8(2));
-                tmp2 = _mm_subs_epu8(first_len, _mm_set1_epi8(2));
-                _range = _mm_or_si128(_range, _mm_alignr_epi8(tmp1, tmp2, 13));
-                let mut _range2 = _mm_setzero_si128();
-                shift1 = _mm_alignr_epi8(_input, input, 15);
-                pos = _mm_sub_epi8(shift1, _mm_set1_epi8(0xEFi32 as i8));
-                tmp1 = _mm_subs_epu8(pos, _mm_set1_epi8(240i32 as i8));
-                _range2 = _mm_shuffle_epi8(df_ee_table, tmp1);
-                tmp2 = _mm_adds_epu8(pos, _mm_set1_epi8(112));
-                _range2 = _mm_add_epi8(_range2, _mm_shuffle_epi8(ef_fe_table, tmp2));
-                _range = _mm_add_epi8(_range, _range2);
-                minv = _mm_shuffle_epi8(range_min_table, _range);
-                maxv = _mm_shuffle_epi8(range_max_table, _range);
-                error = _mm_or_si128(error, _mm_cmplt_epi8(_input, minv));
-                error = _mm_or_si128(error, _mm_cmpgt_epi8(_input, maxv));
-                /* *********************** next iteration **************************/
+                let _input = _mm_lddqu_si128(data.offset(16) as *const __m128i);
+                if ASCII_PATH {
+                    let any = _mm_or_si128(input, _input);
+                    if _mm_testz_si128(any, _mm_set1_epi8(0x80u8 as i8)) != 0 {
+                        // An ASCII block is only an error if the one before it
+                        // was incomplete. After it, nothing is carried over,
+                        // just as if it were zeros.
+                        error = _mm_or_si128(error, _mm_subs_epu8(prev_input, incomplete_max));
+                        prev_input = _mm_setzero_si128();
+                        prev_first_len = _mm_setzero_si128();
+                        data = data.offset(32);
+                        len -= 32;
+                        continue;
+                    }
+                }
+                let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+                error = _mm_or_si128(error, block_error);
+                let (block_error, _first_len) = check_block(_input, input, first_len);
+                error = _mm_or_si128(error, block_error);
+
                 prev_input = _input;
                 prev_first_len = _first_len;
-                data = data.offset(32 as isize);
+                data = data.offset(32);
                 len -= 32
             }
             if _mm_testz_si128(error, error) == 0 {
                 return false;
             }
-            let mut token4: i32 = _mm_extract_epi32(prev_input, 3);
-            let token: *const i8 = &mut token4 as *mut i32 as *const i8;
-            let mut lookahead = 0;
-            if *token.offset(3 as isize) as i32 > 0xBF as i32 as i8 as i32 {
-                lookahead = 1
-            } else if *token.offset(2 as isize) as i32 > 0xBF as i32 as i8 as i32 {
-                lookahead = 2
-            } else if *token.offset(1 as isize) as i32 > 0xBF as i32 as i8 as i32 {
-                lookahead = 3
-            }
-            data = data.offset(-(lookahead as isize));
-            len += lookahead
         }
-        return libcore::is_utf8(core::slice::from_raw_parts(data, len));
+        // last part, at most 31 bytes
+        if len >= 16 {
+            let input = _mm_lddqu_si128(data as *const __m128i);
+            let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+            error = _mm_or_si128(error, block_error);
+            prev_input = input;
+            prev_first_len = first_len;
+            len -= 16;
+        }
+        if len > 0 {
+            let input = tail::sse(bytes, bytes.len() - len);
+            let (block_error, _) = check_block(input, prev_input, prev_first_len);
+            error = _mm_or_si128(error, block_error);
+            prev_input = input;
+        }
+        error = _mm_or_si128(error, _mm_subs_epu8(prev_input, incomplete_max));
+        _mm_testz_si128(error, error) != 0
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support SSE2, SSSE3 and SSE4.1.
+#[target_feature(enable = "sse2,ssse3,sse4.1")]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
     }
 }
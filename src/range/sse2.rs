@@ -0,0 +1,259 @@
+//! SSE2 implementation of the range algorithm, with comparisons in place of
+//! table lookups.
+//!
+//! Every x86-64 CPU supports SSE2, so unlike the other x86 backends this one
+//! is available without any runtime detection or special `RUSTFLAGS`, and its
+//! functions are safe wherever SSE2 is enabled at compile time. It cannot use
+//! `pshufb`, which is SSSE3, so unlike the other range backends it does not
+//! look up the range of each byte in a table. It checks the same ranges by
+//! comparing the bytes against their bounds directly:
+//!
+//! * A byte must be a continuation (80 ~ BF) exactly if it is the second byte
+//!   after a byte of at least C0, the third after one of at least E0 or the
+//!   fourth after one of at least F0.
+//! * C0, C1 and F5 ~ FF are never allowed.
+//! * The Second Byte must be A0 ~ BF after E0, 80 ~ 9F after ED, 90 ~ BF
+//!   after F0 and 80 ~ 8F after F4.
+//!
+//! SSE2 only compares signed bytes, so both sides of an unsigned comparison
+//! are flipped by 0x80 first. Blocks of 64 ASCII bytes are skipped.
+//!
+//! ## Target specific intrinsics used:
+//! ### SSE2
+//! * _mm_and_si128
+//! * _mm_cmpeq_epi8
+//! * _mm_cmpgt_epi8
+//! * _mm_cmplt_epi8
+//! * _mm_loadu_si128
+//! * _mm_movemask_epi8
+//! * _mm_or_si128
+//! * _mm_set1_epi8
+//! * _mm_setzero_si128
+//! * _mm_slli_si128
+//! * _mm_srli_si128
+//! * _mm_xor_si128
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+
+#[inline(always)]
+fn load(bytes: &[u8]) -> __m128i {
+    debug_assert!(bytes.len() >= 16);
+    unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) }
+}
+
+// (prev, input) << 1 byte
+#[inline(always)]
+fn prev1(prev: __m128i, input: __m128i) -> __m128i {
+    unsafe { _mm_or_si128(_mm_slli_si128(input, 1), _mm_srli_si128(prev, 16 - 1)) }
+}
+
+// (prev, input) << 2 bytes
+#[inline(always)]
+fn prev2(prev: __m128i, input: __m128i) -> __m128i {
+    unsafe { _mm_or_si128(_mm_slli_si128(input, 2), _mm_srli_si128(prev, 16 - 2)) }
+}
+
+// (prev, input) << 3 bytes
+#[inline(always)]
+fn prev3(prev: __m128i, input: __m128i) -> __m128i {
+    unsafe { _mm_or_si128(_mm_slli_si128(input, 3), _mm_srli_si128(prev, 16 - 3)) }
+}
+
+// Unsigned `flipped > bound`, where `flipped` are the bytes xor 0x80
+#[inline(always)]
+fn gt(flipped: __m128i, bound: u8) -> __m128i {
+    unsafe { _mm_cmpgt_epi8(flipped, _mm_set1_epi8((bound ^ 0x80) as i8)) }
+}
+
+// Unsigned `flipped < bound`, where `flipped` are the bytes xor 0x80
+#[inline(always)]
+fn lt(flipped: __m128i, bound: u8) -> __m128i {
+    unsafe { _mm_cmplt_epi8(flipped, _mm_set1_epi8((bound ^ 0x80) as i8)) }
+}
+
+// Bytes of `prev` equal to `byte`
+#[inline(always)]
+fn eq(prev: __m128i, byte: u8) -> __m128i {
+    unsafe { _mm_cmpeq_epi8(prev, _mm_set1_epi8(byte as i8)) }
+}
+
+struct State {
+    prev_input: __m128i,
+    // Bytes of at least C0, E0 and F0 in `prev_input`
+    prev_lead_2: __m128i,
+    prev_lead_3: __m128i,
+    prev_lead_4: __m128i,
+    error: __m128i,
+}
+
+impl Default for State {
+    #[inline(always)]
+    fn default() -> Self {
+        unsafe {
+            State {
+                prev_input: _mm_setzero_si128(),
+                prev_lead_2: _mm_setzero_si128(),
+                prev_lead_3: _mm_setzero_si128(),
+                prev_lead_4: _mm_setzero_si128(),
+                error: _mm_setzero_si128(),
+            }
+        }
+    }
+}
+
+impl State {
+    #[inline(always)]
+    fn check_bytes(&mut self, input: __m128i) {
+        unsafe {
+            let flipped = _mm_xor_si128(input, _mm_set1_epi8(0x80u8 as i8));
+            let lead_2 = gt(flipped, 0xBF);
+            let lead_3 = gt(flipped, 0xDF);
+            let lead_4 = gt(flipped, 0xEF);
+            /* 80 ~ BF are the only bytes below C0 as signed bytes */
+            let is_cont = _mm_cmplt_epi8(input, _mm_set1_epi8(0xC0u8 as i8));
+            let must_be_cont = _mm_or_si128(
+                _mm_or_si128(
+                    prev1(self.prev_lead_2, lead_2),
+                    prev2(self.prev_lead_3, lead_3),
+                ),
+                prev3(self.prev_lead_4, lead_4),
+            );
+            let mut error = _mm_xor_si128(must_be_cont, is_cont);
+            /* C0, C1 and F5 ~ FF */
+            error = _mm_or_si128(error, _mm_and_si128(lead_2, lt(flipped, 0xC2)));
+            error = _mm_or_si128(error, gt(flipped, 0xF4));
+            /* Second Byte after E0, ED, F0 and F4 */
+            let shift1 = prev1(self.prev_input, input);
+            error = _mm_or_si128(error, _mm_and_si128(eq(shift1, 0xE0), lt(flipped, 0xA0)));
+            error = _mm_or_si128(error, _mm_and_si128(eq(shift1, 0xED), gt(flipped, 0x9F)));
+            error = _mm_or_si128(error, _mm_and_si128(eq(shift1, 0xF0), lt(flipped, 0x90)));
+            error = _mm_or_si128(error, _mm_and_si128(eq(shift1, 0xF4), gt(flipped, 0x8F)));
+
+            self.error = _mm_or_si128(self.error, error);
+            self.prev_input = input;
+            self.prev_lead_2 = lead_2;
+            self.prev_lead_3 = lead_3;
+            self.prev_lead_4 = lead_4;
+        }
+    }
+
+    // ASCII bytes neither start a character nor continue one, so checking
+    // zeros instead finds the same errors: continuations missing before them.
+    #[inline(always)]
+    fn check_ascii(&mut self) {
+        unsafe { self.check_bytes(_mm_setzero_si128()) }
+    }
+
+    #[inline(always)]
+    fn has_error(&self) -> bool {
+        unsafe { _mm_movemask_epi8(self.error) != 0 }
+    }
+
+    #[inline(always)]
+    fn is_valid(&mut self) -> bool {
+        self.check_ascii();
+        unsafe { _mm_movemask_epi8(self.error) == 0 }
+    }
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn check_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut state = State::default();
+
+    // 64 bytes at a time, skipping the checks if they are all ASCII
+    let mut blocks = bytes.chunks_exact(64);
+    while blocks.len() != 0 {
+        for block in (&mut blocks).take(ERROR_CHECK_INTERVAL / 64) {
+            let input_0 = load(&block[..16]);
+            let input_1 = load(&block[16..32]);
+            let input_2 = load(&block[32..48]);
+            let input_3 = load(&block[48..]);
+            let any = _mm_or_si128(
+                _mm_or_si128(input_0, input_1),
+                _mm_or_si128(input_2, input_3),
+            );
+            if _mm_movemask_epi8(any) == 0 {
+                state.check_ascii();
+            } else {
+                state.check_bytes(input_0);
+                state.check_bytes(input_1);
+                state.check_bytes(input_2);
+                state.check_bytes(input_3);
+            }
+        }
+        if state.has_error() {
+            return false;
+        }
+    }
+    let mut chunks = blocks.remainder().chunks_exact(16);
+    for chunk in &mut chunks {
+        state.check_bytes(load(chunk));
+    }
+    // last part
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        state.check_bytes(tail::sse2(bytes, bytes.len() - rest.len()));
+    }
+
+    state.is_valid()
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+#[cfg(target_feature = "sse2")]
+#[inline]
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    // SSE2 is enabled at compile time.
+    unsafe { check_utf8(bytes) }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// # Safety
+///
+/// The CPU must support SSE2.
+#[cfg(not(target_feature = "sse2"))]
+#[inline]
+pub unsafe fn is_utf8(bytes: &[u8]) -> bool {
+    check_utf8(bytes)
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+#[cfg(target_feature = "sse2")]
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+///
+/// # Safety
+///
+/// The CPU must support SSE2.
+#[cfg(not(target_feature = "sse2"))]
+pub unsafe fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -0,0 +1,203 @@
+//! VSX implementation of the range algorithm for little-endian POWER.
+//!
+//! It follows the [NEON version](https://github.com/cyb70289/utf8/blob/master/range-neon.c),
+//! with `vec_perm` in place of `vqtbl1q_u8`. Unlike `vqtbl1q_u8`, `vec_perm`
+//! selects from the 32 bytes of both operands and ignores the top three bits
+//! of each index, so indices that can be 16 or above are clamped to 16 first,
+//! which picks a zero from the second operand.
+//!
+//! The intrinsics are unstable, so this module needs a nightly compiler and
+//! the `powerpc-vsx` feature. It is only compiled if the `vsx` target feature
+//! is enabled, as it is by default on `powerpc64le`, so its functions are
+//! safe to call, and then always used.
+use crate::{libcore, small, tail, Utf8Error, ERROR_CHECK_INTERVAL};
+use core::arch::powerpc64::*;
+use core::{mem, ptr};
+
+// Map high nibble of "First Byte" to legal character length minus 1
+// 0x00 ~ 0xBF --> 0
+// 0xC0 ~ 0xDF --> 1
+// 0xE0 ~ 0xEF --> 2
+// 0xF0 ~ 0xFF --> 3
+static FIRST_LEN_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3];
+// Map "First Byte" to 8-th item of range table (0xC2 ~ 0xF4)
+static FIRST_RANGE_TABLE: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 8, 8, 8];
+// Range table, map range index to min and max values
+// Index 0    : 00 ~ 7F (First Byte, ascii)
+// Index 1,2,3: 80 ~ BF (Second, Third, Fourth Byte)
+// Index 4    : A0 ~ BF (Second Byte after E0)
+// Index 5    : 80 ~ 9F (Second Byte after ED)
+// Index 6    : 90 ~ BF (Second Byte after F0)
+// Index 7    : 80 ~ 8F (Second Byte after F4)
+// Index 8    : C2 ~ F4 (First Byte, non ascii)
+// Index 9~15 : illegal: i >= 255 && i <= 0
+#[rustfmt::skip]
+static RANGE_MIN_TABLE: [u8; 16] = [
+    0x00, 0x80, 0x80, 0x80, 0xA0, 0x80, 0x90, 0x80,
+    0xC2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+#[rustfmt::skip]
+static RANGE_MAX_TABLE: [u8; 16] = [
+    0x7F, 0xBF, 0xBF, 0xBF, 0xBF, 0x9F, 0xBF, 0x8F,
+    0xF4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+// Range index adjustments for the four special First Bytes (E0, ED, F0, F4),
+// after which the Second Byte is not 80 ~ BF.
+// index1 -> E0, index14 -> ED
+static DF_EE_TABLE: [u8; 16] = [0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+// index1 -> F0, index5 -> F4
+static EF_FE_TABLE: [u8; 16] = [0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+#[inline(always)]
+fn load(bytes: &[u8; 16]) -> vector_unsigned_char {
+    unsafe { mem::transmute(*bytes) }
+}
+
+#[inline(always)]
+fn splat(byte: u8) -> vector_unsigned_char {
+    load(&[byte; 16])
+}
+
+// Indices that select the bytes of (prev, input) shifted left by K bytes
+#[inline(always)]
+fn shift_indices<const K: u8>() -> vector_unsigned_char {
+    let mut indices = [0; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        *index = 16 - K + i as u8;
+    }
+    load(&indices)
+}
+
+// 16-byte table lookup, indices of 16 and above must not occur
+#[inline(always)]
+fn lookup(table: vector_unsigned_char, indices: vector_unsigned_char) -> vector_unsigned_char {
+    unsafe { vec_perm(table, table, indices) }
+}
+
+#[inline(always)]
+fn is_zero(v: vector_unsigned_char) -> bool {
+    unsafe { mem::transmute::<vector_unsigned_char, u128>(v) == 0 }
+}
+
+// Checks the 16 bytes of `input`, which follow `prev_input`. Returns the
+// errors found and the lengths of the characters that start in `input`.
+#[inline(always)]
+fn check_block(
+    input: vector_unsigned_char,
+    prev_input: vector_unsigned_char,
+    prev_first_len: vector_unsigned_char,
+) -> (vector_unsigned_char, vector_unsigned_char) {
+    unsafe {
+        let zero = splat(0);
+        let first_len_tbl = load(&FIRST_LEN_TABLE);
+        let first_range_tbl = load(&FIRST_RANGE_TABLE);
+        let range_min_tbl = load(&RANGE_MIN_TABLE);
+        let range_max_tbl = load(&RANGE_MAX_TABLE);
+        let df_ee_tbl = load(&DF_EE_TABLE);
+        let ef_fe_tbl = load(&EF_FE_TABLE);
+        let shift_1 = shift_indices::<1>();
+        let shift_2 = shift_indices::<2>();
+        let shift_3 = shift_indices::<3>();
+
+        /* high_nibbles = input >> 4 */
+        let high_nibbles = vec_sr(input, splat(4));
+        /* first_len = legal character length minus 1 */
+        /* 0 for 00~7F, 1 for C0~DF, 2 for E0~EF, 3 for F0~FF */
+        let first_len = lookup(first_len_tbl, high_nibbles);
+        /* First Byte: set range index to 8 for bytes within 0xC0 ~ 0xFF */
+        let mut range = lookup(first_range_tbl, high_nibbles);
+        /* Second Byte: set range index to first_len */
+        /* range |= (first_len, prev_first_len) << 1 byte */
+        range = vec_or(range, vec_perm(prev_first_len, first_len, shift_1));
+        /* Third Byte: set range index to saturate_sub(first_len, 1) */
+        let mut tmp1 = vec_subs(first_len, splat(1));
+        let mut tmp2 = vec_subs(prev_first_len, splat(1));
+        /* range |= (tmp1, tmp2) << 2 bytes */
+        range = vec_or(range, vec_perm(tmp2, tmp1, shift_2));
+        /* Fourth Byte: set range index to saturate_sub(first_len, 2) */
+        tmp1 = vec_subs(first_len, splat(2));
+        tmp2 = vec_subs(prev_first_len, splat(2));
+        /* range |= (tmp1, tmp2) << 3 bytes */
+        range = vec_or(range, vec_perm(tmp2, tmp1, shift_3));
+        /*
+         * Adjust Second Byte range for special First Bytes(E0,ED,F0,F4)
+         * Overlaps lead to index 9~15, which are illegal in range table
+         */
+        let shift1 = vec_perm(prev_input, input, shift_1);
+        let pos = vec_sub(shift1, splat(0xEF));
+        /*
+         * shift1:  | EF  F0 ... FE | FF  00  ... ...  DE | DF  E0 ... EE |
+         * pos:     | 0   1      15 | 16  17           239| 240 241    255|
+         * pos-240: | 0   0      0  | 0   0            0  | 0   1      15 |
+         */
+        tmp1 = vec_subs(pos, splat(240));
+        let mut range2 = lookup(df_ee_tbl, tmp1);
+        /* Index 16 selects a zero */
+        range2 = vec_add(range2, vec_perm(ef_fe_tbl, zero, vec_min(pos, splat(16))));
+        range = vec_add(range, range2);
+        /* Load min and max values per calculated range index */
+        let minv = lookup(range_min_tbl, range);
+        let maxv = lookup(range_max_tbl, range);
+        /* Check value range */
+        let below: vector_unsigned_char = mem::transmute(vec_cmplt(input, minv));
+        let above: vector_unsigned_char = mem::transmute(vec_cmpgt(input, maxv));
+        (vec_or(below, above), first_len)
+    }
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < 16 {
+        return small::is_utf8(bytes);
+    }
+    let mut data = bytes.as_ptr();
+    let mut len = bytes.len();
+    unsafe {
+        let zero = splat(0);
+        let mut prev_input = zero;
+        let mut prev_first_len = zero;
+        let mut error = zero;
+
+        while len >= 16 {
+            let stop = len.saturating_sub(ERROR_CHECK_INTERVAL).max(15);
+            while len > stop {
+                let input: vector_unsigned_char = ptr::read_unaligned(data as *const _);
+                let (block_error, first_len) = check_block(input, prev_input, prev_first_len);
+                error = vec_or(error, block_error);
+
+                prev_input = input;
+                prev_first_len = first_len;
+                data = data.add(16);
+                len -= 16;
+            }
+            if !is_zero(error) {
+                return false;
+            }
+        }
+        // last part
+        if len > 0 {
+            let input = tail::vsx(bytes, bytes.len() - len);
+            let (block_error, _) = check_block(input, prev_input, prev_first_len);
+            error = vec_or(error, block_error);
+            prev_input = input;
+        }
+        let incomplete_max: vector_unsigned_char =
+            ptr::read_unaligned(tail::INCOMPLETE_MAX[16..].as_ptr() as *const _);
+        error = vec_or(error, vec_subs(prev_input, incomplete_max));
+        is_zero(error)
+    }
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
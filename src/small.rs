@@ -0,0 +1,82 @@
+//! Validation of inputs too short for vector code to pay off.
+//!
+//! ASCII words are skipped 8 bytes at a time, as in the [SWAR
+//! implementation](crate::swar). The bytes from the first word that is not
+//! ASCII are run through a DFA without any branches: the transitions of a
+//! byte from all states are packed into one `u64`, 6 bits per state, and each
+//! state is the offset of its own bits. The next state is then the word of
+//! the byte shifted right by the current state, whose low 6 bits are all that
+//! the next shift looks at.
+use crate::swar::{load, HIGH};
+
+/// The length below which [`is_utf8`](crate::is_utf8) uses this module
+/// rather than a vector backend. The vector backends use it for those inputs
+/// too. From 16 bytes on, their loads of the tail are faster.
+pub(crate) const MAX_LEN: usize = 16;
+
+// The states, as offsets into the transition words. Those after `ACCEPT` are
+// named after what they still expect: one to three continuations, or the
+// Second Byte after E0, ED, F0 or F4 and then one or two continuations.
+const ERROR: u32 = 0;
+const ACCEPT: u32 = 6;
+const CONT_1: u32 = 12;
+const CONT_2: u32 = 18;
+const CONT_3: u32 = 24;
+const AFTER_E0: u32 = 30;
+const AFTER_ED: u32 = 36;
+const AFTER_F0: u32 = 42;
+const AFTER_F4: u32 = 48;
+
+const fn next(state: u32, byte: u8) -> u32 {
+    match (state, byte) {
+        (ACCEPT, 0x00..=0x7F) => ACCEPT,
+        (ACCEPT, 0xC2..=0xDF) => CONT_1,
+        (ACCEPT, 0xE0) => AFTER_E0,
+        (ACCEPT, 0xE1..=0xEC) | (ACCEPT, 0xEE..=0xEF) => CONT_2,
+        (ACCEPT, 0xED) => AFTER_ED,
+        (ACCEPT, 0xF0) => AFTER_F0,
+        (ACCEPT, 0xF1..=0xF3) => CONT_3,
+        (ACCEPT, 0xF4) => AFTER_F4,
+        (CONT_1, 0x80..=0xBF) => ACCEPT,
+        (CONT_2, 0x80..=0xBF) | (AFTER_E0, 0xA0..=0xBF) | (AFTER_ED, 0x80..=0x9F) => CONT_1,
+        (CONT_3, 0x80..=0xBF) | (AFTER_F0, 0x90..=0xBF) | (AFTER_F4, 0x80..=0x8F) => CONT_2,
+        _ => ERROR,
+    }
+}
+
+// The next state from every state, for each byte. `ERROR` leads to itself
+// as its bits are all zero.
+static TRANSITIONS: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut state = ACCEPT;
+        while state <= AFTER_F4 {
+            table[byte] |= (next(state, byte as u8) as u64) << state;
+            state += 6;
+        }
+        byte += 1;
+    }
+    table
+};
+
+/// Returns `true` if `bytes` is valid UTF-8.
+///
+/// It is correct for any input, but only fast for those shorter than
+/// [`MAX_LEN`].
+pub(crate) fn is_utf8(bytes: &[u8]) -> bool {
+    let mut rest = bytes;
+    while rest.len() >= 8 && load(rest) & HIGH == 0 {
+        rest = &rest[8..];
+    }
+    // The last word may overlap the ones before it, but if it is ASCII too,
+    // so is the whole input.
+    if rest.len() < 8 && bytes.len() >= 8 && load(&bytes[bytes.len() - 8..]) & HIGH == 0 {
+        return true;
+    }
+    let mut state = ACCEPT;
+    for &byte in rest {
+        state = TRANSITIONS[byte as usize].wrapping_shr(state) as u32;
+    }
+    state & 0x3F == ACCEPT
+}
@@ -0,0 +1,154 @@
+//! Validation of input that arrives in chunks.
+use crate::libcore::{self, utf8_char_width};
+use crate::{validate, Utf8Error};
+
+/// The result of [`validate_prefix`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Utf8Prefix {
+    /// The input is valid UTF-8.
+    Complete,
+    /// The input is valid UTF-8, except that it ends in the middle of a
+    /// multi-byte sequence.
+    Incomplete {
+        /// The length of the valid part, which is also where the unfinished
+        /// sequence starts.
+        valid_up_to: usize,
+        /// The number of bytes missing from the unfinished sequence, between
+        /// 1 and 3.
+        needed: usize,
+    },
+    /// The input contains an invalid byte sequence.
+    Invalid(Utf8Error),
+}
+
+/// Checks whether `bytes` is valid UTF-8, telling apart input that is invalid
+/// from input that only ends in the middle of a multi-byte sequence.
+///
+/// This is meant for readers that decode a stream chunk by chunk: when the
+/// result is [`Utf8Prefix::Incomplete`], the bytes from `valid_up_to` on
+/// should be held back and prepended to the next chunk.
+///
+/// ```
+/// use is_utf8::{validate_prefix, Utf8Prefix};
+///
+/// assert_eq!(validate_prefix(b"caf\xC3\xA9"), Utf8Prefix::Complete);
+/// assert_eq!(
+///     validate_prefix(b"caf\xC3"),
+///     Utf8Prefix::Incomplete { valid_up_to: 3, needed: 1 }
+/// );
+/// assert!(matches!(validate_prefix(b"caf\xC3("), Utf8Prefix::Invalid(_)));
+/// ```
+pub fn validate_prefix(bytes: &[u8]) -> Utf8Prefix {
+    match validate(bytes) {
+        Ok(()) => Utf8Prefix::Complete,
+        Err(e) if e.error_len.is_none() => Utf8Prefix::Incomplete {
+            valid_up_to: e.valid_up_to,
+            needed: utf8_char_width(bytes[e.valid_up_to]) - (bytes.len() - e.valid_up_to),
+        },
+        Err(e) => Utf8Prefix::Invalid(e),
+    }
+}
+
+/// A UTF-8 validator for a stream of bytes that arrives in chunks.
+///
+/// The chunks may have any size, and a multi-byte sequence may be split
+/// between two or more of them. Each chunk is checked with
+/// [`validate`](crate::validate), and at most three trailing bytes of an
+/// unfinished sequence are carried over to the next chunk, so nothing needs
+/// to be buffered.
+///
+/// The error returned by [`finish`](Utf8Validator::finish) is the one
+/// [`core::str::from_utf8`] would report for the concatenation of all chunks,
+/// so its offsets are relative to the start of the stream.
+///
+/// ```
+/// use is_utf8::Utf8Validator;
+///
+/// let mut validator = Utf8Validator::new();
+/// validator.update(b"caf\xC3");
+/// validator.update(b"\xA9 au lait");
+/// assert!(validator.finish().is_ok());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Utf8Validator {
+    // Number of bytes before `pending` that are known to be valid.
+    offset: usize,
+    // The start of an unfinished multi-byte sequence.
+    pending: [u8; 4],
+    pending_len: usize,
+    error: Option<Utf8Error>,
+}
+
+impl Utf8Validator {
+    /// Creates a validator for a new stream.
+    #[inline]
+    pub fn new() -> Self {
+        Utf8Validator::default()
+    }
+
+    /// Validates the next chunk of the stream.
+    ///
+    /// Once an error has been found, further chunks are ignored.
+    pub fn update(&mut self, mut chunk: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.pending_len > 0 {
+            // Try to finish the sequence left over from the previous chunk.
+            let width = utf8_char_width(self.pending[0]);
+            let take = chunk.len().min(width - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&chunk[..take]);
+            match libcore::validate(&self.pending[..self.pending_len + take]) {
+                Ok(()) => {
+                    self.offset += width;
+                    self.pending_len = 0;
+                    chunk = &chunk[take..];
+                }
+                Err(e) if e.error_len.is_none() => {
+                    self.pending_len += take;
+                    return;
+                }
+                Err(e) => {
+                    self.set_error(e);
+                    return;
+                }
+            }
+        }
+        match validate_prefix(chunk) {
+            Utf8Prefix::Complete => self.offset += chunk.len(),
+            Utf8Prefix::Incomplete { valid_up_to, .. } => {
+                let tail = &chunk[valid_up_to..];
+                self.pending[..tail.len()].copy_from_slice(tail);
+                self.pending_len = tail.len();
+                self.offset += valid_up_to;
+            }
+            Utf8Prefix::Invalid(e) => self.set_error(e),
+        }
+    }
+
+    /// Finishes the stream, and returns the first error in it, if any.
+    ///
+    /// An unfinished multi-byte sequence at the end of the stream is reported
+    /// as an error whose [`error_len`](Utf8Error::error_len) is `None`.
+    pub fn finish(self) -> Result<(), Utf8Error> {
+        if let Some(error) = self.error {
+            Err(error)
+        } else if self.pending_len > 0 {
+            Err(Utf8Error {
+                valid_up_to: self.offset,
+                error_len: None,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // Records an error found at `error.valid_up_to` bytes past `self.offset`.
+    #[inline]
+    fn set_error(&mut self, error: Utf8Error) {
+        self.error = Some(Utf8Error {
+            valid_up_to: self.offset + error.valid_up_to,
+            error_len: error.error_len,
+        });
+    }
+}
@@ -0,0 +1,149 @@
+//! Owned string conversions.
+use crate::{from_utf8_unchecked, is_utf8, utf8_chunks, validate, Utf8Error};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A possible error value when converting an owned buffer of bytes to a
+/// string.
+///
+/// This mirrors [`alloc::string::FromUtf8Error`]: it gives back the bytes
+/// that failed to convert together with the [`Utf8Error`] describing why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromUtf8Error {
+    bytes: Vec<u8>,
+    error: Utf8Error,
+}
+
+impl FromUtf8Error {
+    /// Returns a slice of the bytes that were attempted to convert.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes that were attempted to convert.
+    ///
+    /// No allocation is made when the input was a `Vec<u8>` or `Box<[u8]>`.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the [`Utf8Error`] with details about the conversion failure.
+    #[inline]
+    pub fn utf8_error(&self) -> Utf8Error {
+        self.error
+    }
+}
+
+impl fmt::Display for FromUtf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FromUtf8Error {}
+
+/// Converts a vector of bytes to a `String`.
+///
+/// This is a drop-in replacement for [`String::from_utf8`]. The vector is
+/// not copied, and is given back in the error if it is not valid UTF-8.
+#[inline]
+pub fn string_from_utf8(vec: Vec<u8>) -> Result<String, FromUtf8Error> {
+    match validate(&vec) {
+        // `vec` was just validated.
+        Ok(()) => Ok(unsafe { String::from_utf8_unchecked(vec) }),
+        Err(error) => Err(FromUtf8Error { bytes: vec, error }),
+    }
+}
+
+/// Converts a boxed slice of bytes to a boxed string slice.
+///
+/// The allocation is reused, and is given back in the error as a `Vec<u8>`
+/// if it is not valid UTF-8.
+#[inline]
+pub fn into_boxed_str(boxed: Box<[u8]>) -> Result<Box<str>, FromUtf8Error> {
+    string_from_utf8(boxed.into_vec()).map(String::into_boxed_str)
+}
+
+/// Converts a clone-on-write slice of bytes to a clone-on-write string.
+///
+/// Borrowed input stays borrowed and owned input stays owned, so this never
+/// allocates when the input is valid UTF-8. If it is not, owned input is
+/// given back in the error as is, while borrowed input is copied into it.
+#[inline]
+pub fn cow_from_utf8(v: Cow<'_, [u8]>) -> Result<Cow<'_, str>, FromUtf8Error> {
+    match v {
+        Cow::Borrowed(bytes) => match validate(bytes) {
+            // `bytes` was just validated.
+            Ok(()) => Ok(Cow::Borrowed(unsafe { from_utf8_unchecked(bytes) })),
+            Err(error) => Err(FromUtf8Error {
+                bytes: bytes.to_vec(),
+                error,
+            }),
+        },
+        Cow::Owned(vec) => string_from_utf8(vec).map(Cow::Owned),
+    }
+}
+
+/// What to put in place of invalid byte sequences when decoding lossily.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// Replace each invalid sequence with U+FFFD REPLACEMENT CHARACTER, like
+    /// [`String::from_utf8_lossy`] does.
+    ReplacementCharacter,
+    /// Replace each invalid sequence with the given character.
+    Char(char),
+    /// Drop invalid sequences.
+    Remove,
+}
+
+impl Default for Replacement {
+    #[inline]
+    fn default() -> Self {
+        Replacement::ReplacementCharacter
+    }
+}
+
+/// Converts a slice of bytes to a string, replacing invalid sequences with
+/// U+FFFD REPLACEMENT CHARACTER.
+///
+/// This is a drop-in replacement for [`String::from_utf8_lossy`]. Valid input,
+/// the common case, is detected with [`is_utf8`](crate::is_utf8) and borrowed
+/// without copying. Otherwise the input is split up with
+/// [`utf8_chunks`](crate::utf8_chunks).
+#[inline]
+pub fn from_utf8_lossy(v: &[u8]) -> Cow<'_, str> {
+    from_utf8_lossy_with(v, Replacement::ReplacementCharacter)
+}
+
+/// Converts a slice of bytes to a string, handling invalid sequences as
+/// chosen by `replacement`.
+///
+/// Invalid sequences are split up following the "substitution of maximal
+/// subparts" practice of the Unicode Standard, as [`String::from_utf8_lossy`]
+/// does, so each one is replaced with a single character.
+pub fn from_utf8_lossy_with(v: &[u8], replacement: Replacement) -> Cow<'_, str> {
+    if is_utf8(v) {
+        // `v` was just validated.
+        return Cow::Borrowed(unsafe { from_utf8_unchecked(v) });
+    }
+    let mut buf = [0; 4];
+    let replacement = match replacement {
+        Replacement::ReplacementCharacter => '\u{FFFD}'.encode_utf8(&mut buf),
+        Replacement::Char(c) => c.encode_utf8(&mut buf),
+        Replacement::Remove => "",
+    };
+    let mut res = String::with_capacity(v.len());
+    for chunk in utf8_chunks(v) {
+        res.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            res.push_str(replacement);
+        }
+    }
+    Cow::Owned(res)
+}
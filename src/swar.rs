@@ -0,0 +1,161 @@
+//! A portable implementation that checks 8 bytes at a time in a `u64`.
+//!
+//! This is SIMD within a register: every check sets the high bit of the
+//! bytes that fail it, and all checks are done on the whole word at once. It
+//! needs no vector instructions, so it is used on targets that have none.
+//!
+//! * A byte must be a continuation (80 ~ BF) exactly if it follows a byte of
+//!   at least C0, or is the second byte after one of at least E0 or the third
+//!   after one of at least F0. The continuations the end of a word asks for
+//!   are carried over to the next one.
+//! * C0 and C1 are never allowed.
+//! * The Second Byte must be A0 ~ BF after E0, 80 ~ 9F after ED, 90 ~ BF
+//!   after F0 and 80 ~ 8F after F4, and F5 ~ FF are never allowed. These only
+//!   depend on the low nibble of the First Byte and two bits of the Second
+//!   Byte, which are small enough to be compared with additions that never
+//!   carry into the next byte.
+//!
+//! Blocks of 16 ASCII bytes are skipped. Once one is found, the rest of the
+//! run is skipped 32 bytes at a time with aligned loads, like
+//! [`libcore`] does.
+use crate::{libcore, Utf8Error};
+use core::convert::TryInto;
+
+// The high bit of every byte
+pub(crate) const HIGH: u64 = 0x8080_8080_8080_8080;
+
+// `byte` in every byte
+const fn splat(byte: u8) -> u64 {
+    0x0101_0101_0101_0101 * byte as u64
+}
+
+#[inline(always)]
+pub(crate) fn load(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[derive(Default)]
+struct State {
+    prev: u64,
+    // The bytes of the next word that must be continuations
+    carry: u64,
+    error: u64,
+}
+
+impl State {
+    #[inline(always)]
+    fn check_word(&mut self, x: u64) {
+        // The byte before each byte
+        let prev1 = (x << 8) | (self.prev >> 56);
+        // Bits 7 and 6, 7 to 5 and 7 to 4 of the byte before set
+        let lead_2 = prev1 & (prev1 << 1) & HIGH;
+        let lead_3 = lead_2 & (prev1 << 2);
+        let lead_4 = lead_3 & (prev1 << 3);
+        // Bit 7 set and bit 6 clear
+        let is_cont = x & !(x << 1) & HIGH;
+        let must_be_cont = lead_2 | (lead_3 << 8) | (lead_4 << 16) | self.carry;
+        let mut error = must_be_cont ^ is_cont;
+        /* C0 and C1: bits 5 to 1 are clear */
+        error |= lead_2 & !(((prev1 & splat(0x3E)) + splat(0x3E)) << 1);
+        /*
+         * Second Byte after E0 ~ EF: the nibble of the First Byte is 0x0 for E0
+         * if bit 5 of the Second Byte is clear (80 ~ 9F), and 0xD for ED if it
+         * is set (A0 ~ BF).
+         */
+        let nibble = prev1 & splat(0x0F);
+        let bit_5 = (x << 2) & HIGH;
+        let e = nibble ^ ((bit_5 >> 7) * 0x0D);
+        error |= lead_3 & !(lead_4 | ((e + splat(0x0F)) << 3));
+        /*
+         * Second Byte after F0 ~ FF: (nibble, bits 5 and 4) is 0x01 ~ 0x10
+         * exactly for F0 90 ~ F4 8F, so adding 0x6F to it gives 0x70 ~ 0x7F.
+         */
+        let f = ((nibble << 2) | ((x >> 4) & splat(0x03))) + splat(0x6F);
+        error |= lead_4 & (f | !(f << 3));
+
+        self.error |= error;
+        self.prev = x;
+        self.carry = (lead_3 >> 56) | (lead_4 >> 48);
+    }
+
+    // Whether the next word can be skipped if it is ASCII, as no character
+    // before it is incomplete.
+    #[inline(always)]
+    fn is_complete(&self) -> bool {
+        (self.prev & (HIGH << 56)) | self.carry == 0
+    }
+
+    #[inline(always)]
+    fn is_valid(&mut self) -> bool {
+        // Continuations missing at the end show up as errors in the zeros
+        // after it.
+        self.check_word(0);
+        self.error == 0
+    }
+}
+
+// The length of the run of ASCII at the start of `bytes`, which must be
+// aligned to 8 bytes, rounded down to a multiple of 32 bytes.
+#[inline(always)]
+fn ascii_len(bytes: &[u8]) -> usize {
+    // Any bits are a valid `u64`.
+    let (head, words, _) = unsafe { bytes.align_to::<u64>() };
+    debug_assert!(head.is_empty());
+    let mut len = 0;
+    for block in words.chunks_exact(4) {
+        if (block[0] | block[1] | block[2] | block[3]) & HIGH != 0 {
+            break;
+        }
+        len += 32;
+    }
+    len
+}
+
+/// Returns `true` if `bytes` is valid UTF-8.
+pub fn is_utf8(bytes: &[u8]) -> bool {
+    let mut state = State::default();
+
+    // 16 bytes at a time, skipping the checks if they are all ASCII
+    let mut i = 0;
+    while bytes.len() - i >= 16 {
+        let word_0 = load(&bytes[i..]);
+        let word_1 = load(&bytes[i + 8..]);
+        i += 16;
+        if (word_0 | word_1) & HIGH == 0 && state.is_complete() {
+            // Skip the rest of the run in a tighter loop of aligned loads,
+            // from the last aligned byte of the block.
+            let start = i - (bytes[i..].as_ptr() as usize % 8);
+            i = i.max(start + ascii_len(&bytes[start..]));
+            continue;
+        }
+        state.check_word(word_0);
+        state.check_word(word_1);
+    }
+    let mut words = bytes[i..].chunks_exact(8);
+    for word in &mut words {
+        state.check_word(load(word));
+    }
+    // last part
+    let rest = words.remainder();
+    if !rest.is_empty() {
+        let mut buffer = [0; 8];
+        buffer[..rest.len()].copy_from_slice(rest);
+        state.check_word(load(&buffer));
+    }
+
+    state.is_valid()
+}
+
+/// Checks whether `bytes` is valid UTF-8, and if not, where the first error
+/// is.
+///
+/// The input is checked with [`is_utf8`] first. Only if that fails is it
+/// scanned again with [`libcore::validate`] to locate the error, so the error
+/// is exactly the one [`core::str::from_utf8`] would report.
+pub fn validate(bytes: &[u8]) -> Result<(), Utf8Error> {
+    if is_utf8(bytes) {
+        Ok(())
+    } else {
+        libcore::validate(bytes)
+    }
+}
@@ -0,0 +1,171 @@
+//! Loading the last bytes of the input, which do not fill a vector, without
+//! copying them into a buffer.
+//!
+//! The 16 bytes that end the input are loaded instead, which is in bounds as
+//! long as the input is at least that long, and moved down with a table
+//! lookup until the first byte of the tail is first. Zeros are moved in
+//! behind it, so the vector is the same as if the tail had been copied into
+//! a zeroed buffer.
+//!
+//! The bound that the last block of the input is checked against also lives
+//! here, as several algorithms share it.
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+// The 16 indices from offset k move the bytes of a 16-byte vector down by k,
+// with zeros moved in. Indices of 16 and above give zero in every table
+// lookup used here.
+#[rustfmt::skip]
+static SHIFT_DOWN: [u8; 32] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+    0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+];
+
+// A block is incomplete if its last three bytes are greater than these, i.e.
+// if it ends with ... 1111____ 111_____ 11______. Used by the lookup algorithm,
+// by the ASCII paths of the range algorithm, and on the last block of the
+// input. A tail that is loaded with zeros after it needs no such check, since
+// the zeros already end an incomplete character.
+#[rustfmt::skip]
+pub(crate) static INCOMPLETE_MAX: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0 - 1, 0xE0 - 1, 0xC0 - 1,
+];
+
+// The last 16 bytes of `bytes`, and the indices that move `bytes[start..]`
+// to the front of them.
+#[inline(always)]
+fn last_16(bytes: &[u8], start: usize) -> (&[u8], &[u8]) {
+    let len = bytes.len();
+    debug_assert!(len - start <= 16);
+    (&bytes[len - 16..], &SHIFT_DOWN[16 - (len - start)..][..16])
+}
+
+/// `bytes[start..]` followed by zeros, with SSSE3. `bytes` must be at least
+/// 16 bytes long, and `start` at most 16 bytes before its end.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub(crate) fn sse(bytes: &[u8], start: usize) -> __m128i {
+    let (last, indices) = last_16(bytes, start);
+    unsafe {
+        _mm_shuffle_epi8(
+            _mm_loadu_si128(last.as_ptr() as *const __m128i),
+            _mm_loadu_si128(indices.as_ptr() as *const __m128i),
+        )
+    }
+}
+
+/// `bytes[start..]` followed by zeros, with SSE2 only. `bytes` must be at
+/// least 16 bytes long, and `start` at most 16 bytes before its end.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub(crate) fn sse2(bytes: &[u8], start: usize) -> __m128i {
+    let len = bytes.len();
+    debug_assert!(len - start <= 16);
+    let shift = 16 - (len - start);
+    unsafe {
+        let last = _mm_loadu_si128(bytes[len - 16..].as_ptr() as *const __m128i);
+        // SSE2 only shifts whole vectors by a constant number of bytes, so the
+        // two halves are shifted by bits and put back together.
+        let (last, bits) = if shift >= 8 {
+            (_mm_srli_si128(last, 8), shift * 8 - 64)
+        } else {
+            (last, shift * 8)
+        };
+        let low = _mm_srl_epi64(last, _mm_cvtsi32_si128(bits as i32));
+        let high = _mm_sll_epi64(_mm_srli_si128(last, 8), _mm_cvtsi32_si128(64 - bits as i32));
+        _mm_or_si128(low, high)
+    }
+}
+
+/// `bytes[start..]` followed by zeros, with AVX2. `bytes` must be at least
+/// 16 bytes long, and `start` at most 32 bytes before its end.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+pub(crate) fn avx(bytes: &[u8], start: usize) -> __m256i {
+    unsafe {
+        if bytes.len() - start > 16 {
+            _mm256_set_m128i(
+                sse(bytes, start + 16),
+                _mm_loadu_si128(bytes[start..].as_ptr() as *const __m128i),
+            )
+        } else {
+            _mm256_set_m128i(_mm_setzero_si128(), sse(bytes, start))
+        }
+    }
+}
+
+/// `bytes[start..]` followed by zeros. `bytes` must be at least 16 bytes
+/// long, and `start` at most 16 bytes before its end.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) fn neon(bytes: &[u8], start: usize) -> core::arch::aarch64::uint8x16_t {
+    use core::arch::aarch64::*;
+
+    let (last, indices) = last_16(bytes, start);
+    unsafe { vqtbl1q_u8(vld1q_u8(last.as_ptr()), vld1q_u8(indices.as_ptr())) }
+}
+
+/// `bytes[start..]` followed by zeros. `bytes` must be at least 16 bytes
+/// long, and `start` at most 16 bytes before its end.
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[inline(always)]
+pub(crate) fn simd128(bytes: &[u8], start: usize) -> core::arch::wasm32::v128 {
+    use core::arch::wasm32::*;
+
+    let (last, indices) = last_16(bytes, start);
+    unsafe {
+        u8x16_swizzle(
+            v128_load(last.as_ptr() as *const v128),
+            v128_load(indices.as_ptr() as *const v128),
+        )
+    }
+}
+
+/// `bytes[start..]` followed by zeros. `bytes` must be at least 16 bytes
+/// long, and `start` at most 16 bytes before its end.
+#[cfg(all(feature = "armv7-neon", target_arch = "arm", target_feature = "neon"))]
+#[inline(always)]
+pub(crate) fn armv7(bytes: &[u8], start: usize) -> core::arch::arm::uint8x16_t {
+    use core::arch::arm::*;
+
+    let (last, indices) = last_16(bytes, start);
+    unsafe {
+        let last = uint8x8x2_t(vld1_u8(last.as_ptr()), vld1_u8(last.as_ptr().add(8)));
+        vcombine_u8(
+            vtbl2_u8(last, vld1_u8(indices.as_ptr())),
+            vtbl2_u8(last, vld1_u8(indices.as_ptr().add(8))),
+        )
+    }
+}
+
+/// `bytes[start..]` followed by zeros. `bytes` must be at least 16 bytes
+/// long, and `start` at most 16 bytes before its end.
+#[cfg(all(
+    feature = "powerpc-vsx",
+    target_arch = "powerpc64",
+    target_endian = "little",
+    target_feature = "vsx"
+))]
+#[inline(always)]
+pub(crate) fn vsx(bytes: &[u8], start: usize) -> core::arch::powerpc64::vector_unsigned_char {
+    use core::arch::powerpc64::*;
+    use core::{mem, ptr};
+
+    let (last, indices) = last_16(bytes, start);
+    unsafe {
+        let last: vector_unsigned_char = ptr::read_unaligned(last.as_ptr() as *const _);
+        let indices: vector_unsigned_char = ptr::read_unaligned(indices.as_ptr() as *const _);
+        // `vec_perm` selects from both operands and ignores the top three
+        // bits of each index, so the zeros come from the second one.
+        let zero: vector_unsigned_char = mem::transmute([0u8; 16]);
+        let max: vector_unsigned_char = mem::transmute([16u8; 16]);
+        vec_perm(last, zero, vec_min(indices, max))
+    }
+}